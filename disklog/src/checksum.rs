@@ -13,3 +13,10 @@ pub fn calculate(position: LogPosition, item_size: u32) -> Checksum {
         ^ Checksum::from_le_bytes([item_size_bytes[0], item_size_bytes[1]])
         ^ Checksum::from_le_bytes([item_size_bytes[2], item_size_bytes[3]])
 }
+
+pub type PayloadChecksum = u32;
+
+/// Extend a running CRC32C of an item's contents with the next chunk of bytes.
+pub fn update_payload(checksum: PayloadChecksum, bytes: &[u8]) -> PayloadChecksum {
+    crc32c::crc32c_append(checksum, bytes)
+}
@@ -0,0 +1,187 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::open::read_tail_file;
+use crate::segment::{list_segments, Segments};
+use crate::LogPosition;
+
+/// How often the tail file is read when it cannot be watched.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod watch {
+    use std::ffi::OsStr;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::path::Path;
+
+    use nix::errno::Errno;
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+    use tokio::io::unix::AsyncFd;
+    use tokio::io::Interest;
+
+    /// Closes the inotify instance when dropped, which `Inotify` does not do itself.
+    struct InotifyFd(Inotify);
+
+    impl AsRawFd for InotifyFd {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_raw_fd()
+        }
+    }
+
+    impl Drop for InotifyFd {
+        fn drop(&mut self) {
+            let _ = nix::unistd::close(self.0.as_raw_fd());
+        }
+    }
+
+    /// Watches the log directory for writes to the tail file. The directory is watched rather
+    /// than the file so a tail file which is replaced is still seen.
+    pub(crate) struct TailWatch {
+        fd: AsyncFd<InotifyFd>,
+    }
+
+    impl TailWatch {
+        pub(super) fn new(path: &Path) -> std::io::Result<TailWatch> {
+            let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+                .map_err(std::io::Error::other)?;
+            let inotify = InotifyFd(inotify);
+            inotify
+                .0
+                .add_watch(
+                    path,
+                    AddWatchFlags::IN_MODIFY
+                        | AddWatchFlags::IN_CREATE
+                        | AddWatchFlags::IN_MOVED_TO,
+                )
+                .map_err(std::io::Error::other)?;
+            // Newer tokio versions deprecate this in favour of an unsafe constructor, as safe code
+            // could close the fd while it is registered. `InotifyFd` only closes it when dropped.
+            #[allow(deprecated)]
+            let fd = AsyncFd::with_interest(inotify, Interest::READABLE)?;
+            Ok(TailWatch { fd })
+        }
+
+        /// Waits until the tail file has been written to.
+        pub(super) async fn changed(&mut self) -> std::io::Result<()> {
+            loop {
+                let mut guard = self.fd.readable().await?;
+                match guard.get_inner().0.read_events() {
+                    Ok(events) => {
+                        if events
+                            .iter()
+                            .any(|event| event.name.as_deref() == Some(OsStr::new("tail")))
+                        {
+                            return Ok(());
+                        }
+                    }
+                    Err(nix::Error::Sys(Errno::EAGAIN)) => guard.clear_ready(),
+                    Err(e) => return Err(std::io::Error::other(e)),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod watch {
+    use std::path::Path;
+
+    /// Tail files can only be watched on Linux, elsewhere they are polled.
+    pub(crate) struct TailWatch;
+
+    impl TailWatch {
+        pub(super) fn new(_path: &Path) -> std::io::Result<TailWatch> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Watching files is not supported",
+            ))
+        }
+
+        pub(super) async fn changed(&mut self) -> std::io::Result<()> {
+            std::future::pending().await
+        }
+    }
+}
+
+/// Wakes the follower whenever the tail might have moved.
+pub(crate) enum Wakeup {
+    Watch(watch::TailWatch),
+    Poll(tokio::time::Interval),
+}
+
+impl Wakeup {
+    /// Watches the tail file if possible, falling back to polling.
+    pub(crate) fn new(path: &Path) -> Wakeup {
+        watch::TailWatch::new(path)
+            .map(Wakeup::Watch)
+            .unwrap_or_else(|_| Wakeup::poll())
+    }
+
+    fn poll() -> Wakeup {
+        Wakeup::Poll(tokio::time::interval(POLL_INTERVAL))
+    }
+
+    async fn wait(&mut self) {
+        match self {
+            Wakeup::Watch(watch) => {
+                if watch.changed().await.is_err() {
+                    *self = Wakeup::poll();
+                }
+            }
+            Wakeup::Poll(interval) => {
+                interval.tick().await;
+            }
+        }
+    }
+}
+
+/// Brings the segments up to date with the log directory, keeping those up to the tail.
+async fn refresh_segments(
+    path: &Path,
+    segments: &Segments,
+    tail_pos: LogPosition,
+) -> std::io::Result<()> {
+    let bases = list_segments(path).await?;
+
+    let mut known = segments.bases();
+    while known.len() > 1 && bases.binary_search(&known[0]).is_err() {
+        segments.remove_first();
+        known.remove(0);
+    }
+
+    let last = *known.last().expect("Log has no segments");
+    for base in bases {
+        if base > last && base <= tail_pos {
+            segments.push(base);
+        }
+    }
+    Ok(())
+}
+
+/// Publishes the tail to readers each time another process moves it, until every reader has
+/// been dropped.
+pub(crate) async fn follow_tail(
+    path: Box<Path>,
+    segments: Arc<Segments>,
+    tail_sender: tokio::sync::watch::Sender<LogPosition>,
+    mut wakeup: Wakeup,
+) {
+    loop {
+        tokio::select! {
+            _ = tail_sender.closed() => return,
+            _ = wakeup.wait() => {}
+        }
+
+        // The tail file can be briefly unreadable while it is replaced, try again next time
+        let tail_pos = match read_tail_file(&path).await {
+            Ok(record) => record.position,
+            Err(_) => continue,
+        };
+        if tail_pos > *tail_sender.borrow()
+            && refresh_segments(&path, &segments, tail_pos).await.is_ok()
+        {
+            let _ = tail_sender.send(tail_pos);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use crate::OpenError;
+
+/// The version of the on disk format written by this library.
+pub const FORMAT_VERSION: u32 = 6;
+
+pub(crate) const LOG_MAGIC: [u8; 4] = *b"DLOG";
+pub(crate) const TAIL_MAGIC: [u8; 4] = *b"DTAL";
+
+/// Magic, format version, creation time and flags.
+pub(crate) const FILE_HEADER_SIZE: u64 = 4 + 4 + 8 + 4;
+
+pub(crate) async fn write_file_header(file: &mut File, magic: &[u8; 4]) -> Result<(), OpenError> {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let mut header = Vec::with_capacity(FILE_HEADER_SIZE as usize);
+    header.extend_from_slice(magic);
+    header.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+    header.extend_from_slice(&created.to_be_bytes());
+    header.extend_from_slice(&0u32.to_be_bytes());
+
+    file.write_all(&header)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))
+}
+
+/// Writes a new version of a file next to it and renames it over the old one, so readers see
+/// either the old file or the whole new one. `contents` follow the file header. Returns the new
+/// file, positioned at its end.
+pub(crate) async fn replace_file(
+    path: &Path,
+    magic: &[u8; 4],
+    contents: &[u8],
+) -> Result<File, OpenError> {
+    let mut replacement_path = path.as_os_str().to_os_string();
+    replacement_path.push(".new");
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .read(true)
+        .write(true)
+        .open(&replacement_path)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    write_file_header(&mut file, magic).await?;
+    file.write_all(contents)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    file.sync_all()
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    tokio::fs::rename(&replacement_path, path)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    Ok(file)
+}
+
+/// Updates the format version of a file which is otherwise unchanged between versions.
+pub(crate) async fn write_file_version(file: &mut File, version: u32) -> std::io::Result<()> {
+    file.seek(std::io::SeekFrom::Start(4)).await?;
+    file.write_u32(version).await?;
+    file.flush().await
+}
+
+/// Returns the format version, or `None` if the magic bytes are missing.
+///
+/// The creation time and flags are informational and not needed to read the file.
+pub(crate) fn parse_file_version(bytes: &[u8], magic: &[u8; 4]) -> Option<u32> {
+    if bytes.len() < FILE_HEADER_SIZE as usize || bytes[0..4] != magic[..] {
+        return None;
+    }
+
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&bytes[4..8]);
+    Some(u32::from_be_bytes(version))
+}
+
+/// Checks a parsed version is one we can read and write.
+pub(crate) fn check_file_version(version: Option<u32>) -> Result<(), OpenError> {
+    match version {
+        None => Err(OpenError::InvalidFileHeader),
+        Some(version) if version != FORMAT_VERSION => Err(OpenError::UnsupportedVersion(version)),
+        Some(_) => Ok(()),
+    }
+}
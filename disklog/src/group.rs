@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use tokio::io::AsyncRead;
+use tokio::sync::Mutex;
+
+use crate::writer::{Error, SegmentWriter, TailWriter, Writer};
+use crate::{LogPosition, Metadata};
+
+/// What has been written but not necessarily committed yet.
+struct Written {
+    write_pos: LogPosition,
+    segment_base: LogPosition,
+    /// A second handle to the segment being written to, so it can be synced without
+    /// holding up other appends.
+    segment_file: Arc<tokio::fs::File>,
+}
+
+/// A writer which can be shared between tasks appending at the same time.
+///
+/// Appends made while another append is syncing are written straight away and then committed
+/// together by a single sync of the log and tail files, rather than each paying for their own.
+pub struct GroupWriter {
+    segment_writer: Mutex<SegmentWriter>,
+    tail_writer: Mutex<TailWriter>,
+    written: std::sync::Mutex<Written>,
+}
+
+impl GroupWriter {
+    pub async fn new(writer: Writer) -> Result<GroupWriter, Error> {
+        let Writer {
+            segment_writer,
+            tail_writer,
+        } = writer;
+        let segment_file = segment_writer
+            .log_file
+            .try_clone()
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+
+        Ok(GroupWriter {
+            written: std::sync::Mutex::new(Written {
+                write_pos: segment_writer.write_pos,
+                segment_base: segment_writer.segment_base,
+                segment_file: Arc::new(segment_file),
+            }),
+            segment_writer: Mutex::new(segment_writer),
+            tail_writer: Mutex::new(tail_writer),
+        })
+    }
+
+    fn written(&self) -> std::sync::MutexGuard<'_, Written> {
+        self.written.lock().expect("Written lock poisoned")
+    }
+
+    async fn update_written(&self, segment_writer: &mut SegmentWriter) -> Result<(), Error> {
+        let segment_changed = self.written().segment_base != segment_writer.segment_base;
+        let segment_file = if segment_changed {
+            let segment_file = segment_writer
+                .log_file
+                .try_clone()
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+            Some(Arc::new(segment_file))
+        } else {
+            None
+        };
+
+        let mut written = self.written();
+        written.write_pos = segment_writer.write_pos;
+        if let Some(segment_file) = segment_file {
+            written.segment_base = segment_writer.segment_base;
+            written.segment_file = segment_file;
+        }
+        Ok(())
+    }
+
+    /// Commits everything written so far, including items written by other appends.
+    async fn commit_written(
+        &self,
+        tail_writer: &mut TailWriter,
+        force_sync: bool,
+    ) -> Result<(), Error> {
+        let (new_tail_pos, segment_file) = {
+            let written = self.written();
+            (written.write_pos, written.segment_file.clone())
+        };
+
+        // Earlier segments were synced before the next was started
+        let sync = force_sync || tail_writer.sync_due(new_tail_pos);
+        if sync {
+            segment_file
+                .sync_data()
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+        }
+
+        tail_writer.commit(new_tail_pos, sync).await
+    }
+
+    pub async fn append<Contents: AsyncRead + Unpin>(
+        &self,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        self.append_checked(None, &Metadata::new(), contents).await
+    }
+
+    /// See `Writer::append_with_metadata`.
+    pub async fn append_with_metadata<Contents: AsyncRead + Unpin>(
+        &self,
+        metadata: &Metadata,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        self.append_checked(None, metadata, contents).await
+    }
+
+    /// See `Writer::append_if`.
+    pub async fn append_if<Contents: AsyncRead + Unpin>(
+        &self,
+        expected_tail: LogPosition,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        self.append_checked(Some(expected_tail), &Metadata::new(), contents)
+            .await
+    }
+
+    /// See `Writer::append_if_with_metadata`.
+    pub async fn append_if_with_metadata<Contents: AsyncRead + Unpin>(
+        &self,
+        expected_tail: LogPosition,
+        metadata: &Metadata,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        self.append_checked(Some(expected_tail), metadata, contents)
+            .await
+    }
+
+    async fn append_checked<Contents: AsyncRead + Unpin>(
+        &self,
+        expected_tail: Option<LogPosition>,
+        metadata: &Metadata,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        let (position, end) = {
+            let mut segment_writer = self.segment_writer.lock().await;
+            // Includes items from other appends which are not committed yet
+            if let Some(expected_tail) = expected_tail {
+                if segment_writer.write_pos != expected_tail {
+                    return Err(Error::TailMoved(segment_writer.write_pos));
+                }
+            }
+            let position = segment_writer.append_item(metadata, contents).await?;
+            self.update_written(&mut segment_writer).await?;
+            (position, segment_writer.write_pos)
+        };
+
+        // Wait for any sync in progress, it might not include this item
+        let mut tail_writer = self.tail_writer.lock().await;
+        if tail_writer.tail_pos < end {
+            self.commit_written(&mut tail_writer, false).await?;
+        }
+
+        Ok(position)
+    }
+
+    /// See `Writer::append_batch`.
+    pub async fn append_batch<Contents: AsyncRead + Unpin>(
+        &self,
+        items: &mut [Contents],
+    ) -> Result<Vec<LogPosition>, Error> {
+        let (positions, end) = {
+            let mut segment_writer = self.segment_writer.lock().await;
+            let positions = segment_writer.append_items(items).await?;
+            self.update_written(&mut segment_writer).await?;
+            (positions, segment_writer.write_pos)
+        };
+
+        let mut tail_writer = self.tail_writer.lock().await;
+        if tail_writer.tail_pos < end {
+            self.commit_written(&mut tail_writer, false).await?;
+        }
+
+        Ok(positions)
+    }
+
+    /// Makes every append so far durable, regardless of the sync policy.
+    pub async fn sync(&self) -> Result<(), Error> {
+        let mut tail_writer = self.tail_writer.lock().await;
+        self.commit_written(&mut tail_writer, true).await
+    }
+
+    /// See `Writer::sync_if_due`.
+    pub async fn sync_if_due(&self) -> Result<(), Error> {
+        let mut tail_writer = self.tail_writer.lock().await;
+        if tail_writer.idle_sync_due() {
+            self.commit_written(&mut tail_writer, true).await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// See `Writer::enforce_retention`.
+    pub async fn enforce_retention(&self) -> Result<(), Error> {
+        self.segment_writer.lock().await.enforce_retention().await
+    }
+}
@@ -1,17 +1,60 @@
 mod checksum;
+mod follow;
+mod format;
+mod metadata;
+mod offsetindex;
 mod open;
+mod recover;
+mod retention;
+mod segment;
+mod timeindex;
+mod verify;
 
+pub mod group;
+pub mod migrate;
 pub mod reader;
+pub mod scan;
 pub mod writer;
 
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use open::{open_log_file, open_tail_file};
+use offsetindex::OffsetIndexWriter;
+use open::{open_segment_file, open_segments, open_tail_file, read_tail_file};
 use reader::ReaderFactory;
-use writer::Writer;
+use segment::{list_segments, Segments};
+use timeindex::TimeIndexWriter;
+use writer::{SegmentWriter, TailWriter, Writer};
+
+pub use format::FORMAT_VERSION;
+pub use metadata::{Metadata, MAX_METADATA_SIZE};
+pub use offsetindex::SequenceNumber;
+pub use open::lock_file_with_timeout;
+pub use retention::RetentionPolicy;
+pub use verify::{verify, Corruption, VerifyReport};
+pub use writer::SyncPolicy;
 
 pub(crate) const U64SIZE: usize = std::mem::size_of::<u64>();
 
+/// Header checksum, record length and record checksum. The record is the timestamp, metadata
+/// length, metadata and contents.
+pub(crate) const ITEM_HEADER_SIZE: u64 = 2 + 4 + 4;
+
+/// Header checksum and record length again after the record, so items can be read backwards.
+pub(crate) const ITEM_TRAILER_SIZE: u64 = 2 + 4;
+
+/// The size of an item with a record of this length, including its header and trailer.
+pub(crate) fn item_size(record_len: u32) -> u64 {
+    ITEM_HEADER_SIZE + record_len as u64 + ITEM_TRAILER_SIZE
+}
+
+/// The timestamp at the start of each record.
+pub(crate) const TIMESTAMP_SIZE: u64 = 8;
+
+/// The metadata length after the timestamp.
+pub(crate) const METADATA_LEN_SIZE: u64 = 2;
+
 pub type LogPosition = u64;
 
 #[derive(Debug)]
@@ -21,6 +64,10 @@ pub enum OpenError {
     CorruptTailPosition,
     /// Indicates a corrupt or mismatched logfile relative to the tail position file.
     LogTooSmall,
+    /// The log was written in a different format version, see `migrate::migrate_log`.
+    UnsupportedVersion(u32),
+    /// A log or tail file did not start with the expected magic bytes.
+    InvalidFileHeader,
 }
 
 impl std::fmt::Display for OpenError {
@@ -30,6 +77,10 @@ impl std::fmt::Display for OpenError {
             OpenError::AlreadyOpen => write!(f, "The log is already open"),
             OpenError::CorruptTailPosition => write!(f, "Corrupt tail position"),
             OpenError::LogTooSmall => write!(f, "Log file was smalled than expected"),
+            OpenError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported log format version {}", version)
+            }
+            OpenError::InvalidFileHeader => write!(f, "Invalid file header"),
         }
     }
 }
@@ -39,26 +90,180 @@ impl std::error::Error for OpenError {}
 pub struct OpenedLog {
     pub writer: Writer,
     pub reader_factory: ReaderFactory,
-    /// Indicates that there was a partial write before the database crashed or was terminated
-    /// without the chance to shutdown. This partial item will be discarded.
-    pub recovered: bool,
+    /// Set when there was a partial write before the database crashed or was terminated without
+    /// the chance to shutdown, or the tail file was rebuilt. The partial write has been discarded.
+    /// Also set when the offset index could not be rebuilt past a corrupt item.
+    pub recovery: Option<RecoveryReport>,
+}
+
+/// What was discarded when the log was opened after a crash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// The number of bytes written after the tail which were truncated.
+    pub discarded_bytes: u64,
+    /// The tail position the log was truncated back to.
+    pub tail_pos: LogPosition,
+    /// The tail file was lost or corrupt and the tail position was found by reading the items,
+    /// see `LogOptions::rebuild_tail`.
+    pub tail_rebuilt: bool,
+    /// The last segment was left without a whole file header by a crash while it was being
+    /// started, so it held no items and was created again.
+    pub segment_recreated: bool,
+    /// Rebuilding the offset index stopped at a corrupt item header at this position, so the
+    /// items from there to the tail have no sequence numbers and cannot be read with
+    /// `ReaderFactory::read_from`. They can still be salvaged with `Reader::next_recovering`.
+    pub unindexed_from: Option<LogPosition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    /// A new segment file is started once the current one holds at least this many bytes.
+    pub segment_size: u64,
+    pub retention: RetentionPolicy,
+    pub sync_policy: SyncPolicy,
+    /// If the tail file is lost or corrupt, find the tail by reading the items in the last
+    /// segment and write a new tail file, instead of failing with `CorruptTailPosition`.
+    ///
+    /// Items which were written but not yet committed when the tail file was lost become part
+    /// of the log.
+    pub rebuild_tail: bool,
+    /// How long to wait for the log to be closed if it is already open, for example by a
+    /// process which is shutting down. With `None` opening fails with `AlreadyOpen` at once.
+    pub lock_timeout: Option<Duration>,
+}
+
+impl Default for LogOptions {
+    fn default() -> LogOptions {
+        LogOptions {
+            segment_size: 64 * 1024 * 1024,
+            retention: RetentionPolicy::default(),
+            sync_policy: SyncPolicy::Always,
+            rebuild_tail: false,
+            lock_timeout: None,
+        }
+    }
 }
 
 pub async fn open_log(path: impl AsRef<Path>) -> Result<OpenedLog, OpenError> {
+    open_log_with_options(path, &LogOptions::default()).await
+}
+
+pub async fn open_log_with_options(
+    path: impl AsRef<Path>,
+    options: &LogOptions,
+) -> Result<OpenedLog, OpenError> {
     let path: Box<Path> = path.as_ref().into();
-    let (tail_file, tail_pos) = open_tail_file(&path).await?;
-    let (log_file, recovered) = open_log_file(&path, tail_pos).await?;
+    let (tail_file, tail_record, tail_rebuilt) =
+        open_tail_file(&path, options.rebuild_tail, options.lock_timeout).await?;
+    let tail_pos = tail_record.position;
+    let (bases, stale_segment_bytes) = open_segments(&path, tail_pos).await?;
+    let segment_base = *bases.last().expect("Log has no segments");
+    let (log_file, truncated_bytes, segment_recreated) =
+        open_segment_file(&path, segment_base, tail_pos).await?;
+    let time_index = TimeIndexWriter::open(&path, tail_pos).await?;
+    let (offset_index, unindexed_from) = OffsetIndexWriter::open(&path, &bases, tail_pos).await?;
 
+    let discarded_bytes = stale_segment_bytes + truncated_bytes;
+    let recovery =
+        if discarded_bytes > 0 || tail_rebuilt || segment_recreated || unindexed_from.is_some() {
+            Some(RecoveryReport {
+                discarded_bytes,
+                tail_pos,
+                tail_rebuilt,
+                segment_recreated,
+                unindexed_from,
+            })
+        } else {
+            None
+        };
+
+    let segments = Arc::new(Segments::new(bases));
     let (tail_sender, tail_recv) = tokio::sync::watch::channel(tail_pos);
 
-    Ok(OpenedLog {
-        writer: Writer {
+    let mut writer = Writer {
+        segment_writer: SegmentWriter {
+            path: path.clone(),
+            segment_size: options.segment_size,
+            segments: segments.clone(),
+            segment_base,
+            retention: options.retention.clone(),
+            time_index,
+            offset_index,
             log_file,
+            write_pos: tail_pos,
+        },
+        tail_writer: TailWriter {
             tail_file,
             tail_sender,
             tail_pos,
+            generation: tail_record.generation,
+            sync_policy: options.sync_policy,
+            unsynced_bytes: 0,
+            last_sync: Instant::now(),
         },
-        reader_factory: ReaderFactory { path, tail_recv },
-        recovered,
+    };
+    writer
+        .enforce_retention()
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+
+    Ok(OpenedLog {
+        writer,
+        reader_factory: ReaderFactory::new(path, segments, tail_recv),
+        recovery,
     })
 }
+
+/// The segments of a log up to the tail, for readers which do not hold the lock.
+async fn readonly_segments(path: &Path, tail_pos: LogPosition) -> Result<Segments, OpenError> {
+    let mut bases = list_segments(path)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    bases.retain(|base| *base <= tail_pos);
+    if bases.is_empty() {
+        return Err(OpenError::LogTooSmall);
+    }
+    Ok(Segments::new(bases))
+}
+
+/// Opens a log for reading only. No lock is taken, so the log can be read while it is open for
+/// writing, for example by inspection tools in another process.
+///
+/// The tail is read once when opening, so items appended afterwards are not seen and readers
+/// waiting for more items end at the tail. Segments removed by the writer's retention policy
+/// can no longer be read. See `open_log_follower` to see new items.
+pub async fn open_log_readonly(path: impl AsRef<Path>) -> Result<ReaderFactory, OpenError> {
+    let path: Box<Path> = path.as_ref().into();
+    let tail_pos = read_tail_file(&path).await?.position;
+    let segments = readonly_segments(&path, tail_pos).await?;
+
+    // Nothing will send a new tail, so readers never wait
+    let (_, tail_recv) = tokio::sync::watch::channel(tail_pos);
+
+    Ok(ReaderFactory::new(path, Arc::new(segments), tail_recv))
+}
+
+/// Like `open_log_readonly`, but follows the tail as another process appends to the log, so
+/// readers waiting for more items see them.
+///
+/// The tail file is watched with inotify on Linux and polled elsewhere, or if it cannot be
+/// watched. A background task follows the tail until the `ReaderFactory` and every reader from
+/// it have been dropped.
+pub async fn open_log_follower(path: impl AsRef<Path>) -> Result<ReaderFactory, OpenError> {
+    let path: Box<Path> = path.as_ref().into();
+
+    // Start watching first so a move of the tail after reading it is not missed
+    let wakeup = follow::Wakeup::new(&path);
+    let tail_pos = read_tail_file(&path).await?.position;
+    let segments = Arc::new(readonly_segments(&path, tail_pos).await?);
+
+    let (tail_sender, tail_recv) = tokio::sync::watch::channel(tail_pos);
+    tokio::spawn(follow::follow_tail(
+        path.clone(),
+        segments.clone(),
+        tail_sender,
+        wakeup,
+    ));
+
+    Ok(ReaderFactory::new(path, segments, tail_recv))
+}
@@ -0,0 +1,91 @@
+use std::convert::TryInto;
+
+/// Key value pairs stored with an item, alongside its contents.
+///
+/// Stored as a sequence of `| u16 key length | key | u16 value length | value |` entries,
+/// at most `MAX_METADATA_SIZE` bytes in total.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    entries: Vec<(String, Vec<u8>)>,
+}
+
+/// The encoded metadata length is stored as a `u16`.
+pub const MAX_METADATA_SIZE: usize = u16::MAX as usize;
+
+impl Metadata {
+    pub fn new() -> Metadata {
+        Metadata::default()
+    }
+
+    /// Sets the value for a key, replacing any previous value.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) {
+        let key = key.into();
+        let value = value.into();
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key, value)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    /// The entries in the order they were first inserted.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns `None` if the encoded metadata would be larger than `MAX_METADATA_SIZE`.
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        for (key, value) in &self.entries {
+            let key_len: u16 = key.len().try_into().ok()?;
+            let value_len: u16 = value.len().try_into().ok()?;
+            bytes.extend_from_slice(&key_len.to_be_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.extend_from_slice(&value_len.to_be_bytes());
+            bytes.extend_from_slice(value);
+        }
+        if bytes.len() > MAX_METADATA_SIZE {
+            None
+        } else {
+            Some(bytes)
+        }
+    }
+
+    /// Returns `None` if the bytes are not valid encoded metadata.
+    pub fn decode(mut bytes: &[u8]) -> Option<Metadata> {
+        fn take<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+            if bytes.len() < 2 {
+                return None;
+            }
+            let len = u16::from_be_bytes([bytes[0], bytes[1]]) as usize;
+            if bytes.len() < 2 + len {
+                return None;
+            }
+            let taken = &bytes[2..2 + len];
+            *bytes = &bytes[2 + len..];
+            Some(taken)
+        }
+
+        let mut metadata = Metadata::new();
+        while !bytes.is_empty() {
+            let key = std::str::from_utf8(take(&mut bytes)?).ok()?;
+            let value = take(&mut bytes)?;
+            metadata.insert(key, value);
+        }
+        Some(metadata)
+    }
+}
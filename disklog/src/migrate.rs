@@ -0,0 +1,338 @@
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use tokio::io::{AsyncReadExt, AsyncSeekExt, BufReader};
+
+use crate::checksum::{calculate, update_payload};
+use crate::format::{parse_file_version, write_file_version, FILE_HEADER_SIZE, TAIL_MAGIC};
+use crate::metadata::Metadata;
+use crate::open::{is_headerless_tail_file, read_copied_log_position, read_log_position};
+use crate::segment::{list_segments, segment_path};
+use crate::writer::{self, ChecksumReader};
+use crate::{
+    item_size, open_log, LogPosition, OpenError, FORMAT_VERSION, METADATA_LEN_SIZE, TIMESTAMP_SIZE,
+};
+
+/// Checksum and length, version 0 items have no contents checksum.
+const V0_ITEM_HEADER_SIZE: u64 = 2 + 4;
+
+/// Checksum, length and contents checksum, used by versions 1 to 5.
+const LEGACY_ITEM_HEADER_SIZE: u64 = 2 + 4 + 4;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(Box<dyn std::error::Error + Send + Sync>),
+    Open(OpenError),
+    Write(writer::Error),
+    /// The item at this position in the old log is corrupt. The old log is left untouched.
+    InvalidItemChecksum(LogPosition),
+    /// The contents of the item at this position in the old log do not match their checksum. The
+    /// old log is left untouched.
+    InvalidPayloadChecksum(LogPosition),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => e.fmt(f),
+            Error::Open(e) => e.fmt(f),
+            Error::Write(e) => e.fmt(f),
+            Error::InvalidItemChecksum(pos) => write!(f, "Item checksum failed at {}", pos),
+            Error::InvalidPayloadChecksum(pos) => {
+                write!(f, "Item contents checksum failed at {}", pos)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Migration {
+    pub from_version: u32,
+    /// The number of items in the migrated log.
+    pub items: u64,
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path
+        .file_name()
+        .expect("Log path has no directory name")
+        .to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// The files an old log's items are stored in, with the position of their first item and the
+/// size of their file header.
+async fn old_log_files(
+    path: &Path,
+    from_version: u32,
+) -> Result<Vec<(LogPosition, PathBuf, u64)>, Error> {
+    if from_version == 0 {
+        return Ok(vec![(0, path.join("log"), 0)]);
+    }
+
+    let bases = list_segments(path)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+    Ok(bases
+        .into_iter()
+        .map(|base| (base, segment_path(path, base), FILE_HEADER_SIZE))
+        .collect())
+}
+
+async fn read_v5_tail_position(tail_file: &mut tokio::fs::File) -> Result<LogPosition, OpenError> {
+    let mut contents = Vec::new();
+    tail_file
+        .seek(std::io::SeekFrom::Start(0))
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    tail_file
+        .read_to_end(&mut contents)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    read_log_position(&contents).map(|record| record.position)
+}
+
+/// Copies a log from an older item format into a new log which then replaces it.
+async fn copy_log(
+    path: &Path,
+    from_version: u32,
+    tail_file: &mut tokio::fs::File,
+) -> Result<u64, Error> {
+    let staging_path = sibling_path(path, ".migrating");
+    let backup_path = sibling_path(path, ".backup");
+
+    // Version 0 items have no contents checksum and the tail file had no header
+    let item_header_size = if from_version == 0 {
+        V0_ITEM_HEADER_SIZE
+    } else {
+        LEGACY_ITEM_HEADER_SIZE
+    };
+    let tail_pos = match from_version {
+        0 => read_copied_log_position(tail_file, 0).await,
+        // Version 5 tail files hold two checksummed records instead of three copies
+        5 => read_v5_tail_position(tail_file).await,
+        _ => read_copied_log_position(tail_file, FILE_HEADER_SIZE).await,
+    }
+    .map_err(Error::Open)?;
+
+    if tokio::fs::metadata(&staging_path).await.is_ok() {
+        tokio::fs::remove_dir_all(&staging_path)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+    }
+
+    let items = {
+        let mut new_log = open_log(&staging_path).await.map_err(Error::Open)?;
+        let files = old_log_files(path, from_version).await?;
+
+        let mut items = 0u64;
+        for (index, (base, file_path, header_size)) in files.iter().enumerate() {
+            let end = files
+                .get(index + 1)
+                .map(|(base, _, _)| *base)
+                .unwrap_or(tail_pos)
+                .min(tail_pos);
+
+            let mut old_log_file = tokio::fs::File::open(file_path)
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+            old_log_file
+                .seek(std::io::SeekFrom::Start(*header_size))
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+            let mut old_log = BufReader::new(old_log_file);
+
+            let mut pos: LogPosition = *base;
+            while pos < end {
+                let checksum = old_log
+                    .read_u16()
+                    .await
+                    .map_err(|e| Error::Io(Box::new(e)))?;
+                let len = old_log
+                    .read_u32()
+                    .await
+                    .map_err(|e| Error::Io(Box::new(e)))?;
+                if calculate(pos, len) != checksum {
+                    return Err(Error::InvalidItemChecksum(pos));
+                }
+                // Checked as the record is copied, so corrupt contents are not given a valid
+                // checksum by the new log
+                let stored_checksum = if from_version > 0 {
+                    let stored_checksum = old_log
+                        .read_u32()
+                        .await
+                        .map_err(|e| Error::Io(Box::new(e)))?;
+                    Some(stored_checksum)
+                } else {
+                    None
+                };
+                let mut payload_checksum = 0;
+
+                // Version 4 records start with a timestamp, older items have none
+                let (timestamp, timestamp_len) = if from_version >= 4 {
+                    let timestamp = old_log
+                        .read_u64()
+                        .await
+                        .map_err(|e| Error::Io(Box::new(e)))?;
+                    payload_checksum = update_payload(payload_checksum, &timestamp.to_be_bytes());
+                    (timestamp, TIMESTAMP_SIZE)
+                } else {
+                    (0, 0)
+                };
+
+                // Version 3 records start with metadata
+                let (metadata, metadata_len) = if from_version >= 3 {
+                    let metadata_len = old_log
+                        .read_u16()
+                        .await
+                        .map_err(|e| Error::Io(Box::new(e)))?;
+                    let mut bytes = vec![0u8; metadata_len as usize];
+                    old_log
+                        .read_exact(&mut bytes)
+                        .await
+                        .map_err(|e| Error::Io(Box::new(e)))?;
+                    payload_checksum =
+                        update_payload(payload_checksum, &metadata_len.to_be_bytes());
+                    payload_checksum = update_payload(payload_checksum, &bytes);
+                    let metadata =
+                        Metadata::decode(&bytes).ok_or(Error::InvalidItemChecksum(pos))?;
+                    (metadata, METADATA_LEN_SIZE + metadata_len as u64)
+                } else {
+                    (Metadata::new(), 0)
+                };
+                let contents_len = (len as u64)
+                    .checked_sub(timestamp_len + metadata_len)
+                    .ok_or(Error::InvalidItemChecksum(pos))?;
+
+                let mut contents = ChecksumReader {
+                    contents: &mut (&mut old_log).take(contents_len),
+                    checksum: payload_checksum,
+                };
+                let new_pos = new_log
+                    .writer
+                    .append_at(timestamp, &metadata, &mut contents)
+                    .await
+                    .map_err(Error::Write)?;
+                if stored_checksum.is_some_and(|stored| stored != contents.checksum) {
+                    return Err(Error::InvalidPayloadChecksum(pos));
+                }
+                let new_len = item_size(
+                    (TIMESTAMP_SIZE
+                        + METADATA_LEN_SIZE
+                        + metadata.encode().map_or(0, |bytes| bytes.len() as u64)
+                        + contents_len) as u32,
+                );
+                if new_log.writer.segment_writer.write_pos - new_pos != new_len {
+                    return Err(Error::Open(OpenError::LogTooSmall));
+                }
+
+                pos += item_header_size + len as u64;
+                items += 1;
+            }
+        }
+        items
+    };
+
+    tokio::fs::rename(path, &backup_path)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+    tokio::fs::rename(&staging_path, path)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+    tokio::fs::remove_dir_all(&backup_path)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+
+    Ok(items)
+}
+
+/// Version 1 stored the whole log in a single file called `log`, which becomes the first segment
+/// of a version 2 log.
+///
+/// Each step can be repeated, so an interrupted upgrade is finished by running it again.
+async fn upgrade_v1_log(path: &Path, tail_file: &mut tokio::fs::File) -> Result<(), Error> {
+    let log_path = path.join("log");
+    if tokio::fs::metadata(&log_path).await.is_ok() {
+        let mut log_file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(&log_path)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        write_file_version(&mut log_file, 2)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        tokio::fs::rename(&log_path, segment_path(path, 0))
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+    }
+
+    write_file_version(tail_file, 2)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))
+}
+
+/// Upgrades a log directory written in an older format so it can be opened with `open_log`.
+///
+/// Logs are copied into a new log next to the old one which then replaces it, so item positions
+/// are not preserved. Items from before items had timestamps are given the Unix epoch as their
+/// timestamp. An interrupted migration can simply be run again.
+///
+/// Returns `None` if the log is already in the current format.
+pub async fn migrate_log(path: impl AsRef<Path>) -> Result<Option<Migration>, Error> {
+    let path = path.as_ref();
+    let backup_path = sibling_path(path, ".backup");
+
+    // We might have been interrupted after moving the old log out of the way
+    if tokio::fs::metadata(path).await.is_err() && tokio::fs::metadata(&backup_path).await.is_ok() {
+        tokio::fs::rename(&backup_path, path)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+    }
+
+    let mut tail_file = match tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path.join("tail"))
+        .await
+    {
+        Ok(tail_file) => tail_file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Error::Io(Box::new(e))),
+    };
+
+    nix::fcntl::flock(
+        tail_file.as_raw_fd(),
+        nix::fcntl::FlockArg::LockExclusiveNonblock,
+    )
+    .map_err(|_| Error::Open(OpenError::AlreadyOpen))?;
+
+    let mut contents = Vec::new();
+    tail_file
+        .read_to_end(&mut contents)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+
+    let from_version = if is_headerless_tail_file(&contents) {
+        0
+    } else {
+        parse_file_version(&contents, &TAIL_MAGIC)
+            .ok_or(Error::Open(OpenError::InvalidFileHeader))?
+    };
+
+    let items = match from_version {
+        FORMAT_VERSION => return Ok(None),
+        0 | 2 | 3 | 4 | 5 => copy_log(path, from_version, &mut tail_file).await?,
+        1 => {
+            upgrade_v1_log(path, &mut tail_file).await?;
+            copy_log(path, 2, &mut tail_file).await?
+        }
+        version => return Err(Error::Open(OpenError::UnsupportedVersion(version))),
+    };
+
+    Ok(Some(Migration {
+        from_version,
+        items,
+    }))
+}
@@ -0,0 +1,360 @@
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::format::{
+    check_file_version, parse_file_version, replace_file, write_file_header, FILE_HEADER_SIZE,
+};
+use crate::reader::read_log_item_header;
+use crate::segment::segment_path;
+use crate::{item_size, LogPosition, OpenError, U64SIZE};
+
+const OFFSET_INDEX_MAGIC: [u8; 4] = *b"DOFF";
+
+/// Item sequence numbers count from zero, the first item appended to the log.
+pub type SequenceNumber = u64;
+
+pub(crate) fn offset_index_path(path: &Path) -> PathBuf {
+    path.join("offsetindex")
+}
+
+/// The sequence number of the first entry follows the file header.
+const FIRST_SEQ_SIZE: u64 = U64SIZE as u64;
+
+fn entry_offset(entry: u64) -> u64 {
+    FILE_HEADER_SIZE + FIRST_SEQ_SIZE + entry * U64SIZE as u64
+}
+
+async fn read_entry(file: &mut File, entry: u64) -> std::io::Result<LogPosition> {
+    file.seek(std::io::SeekFrom::Start(entry_offset(entry)))
+        .await?;
+    file.read_u64().await
+}
+
+/// The number of entries before the first at or after `position`, as entries are in order.
+async fn count_before(
+    file: &mut File,
+    entries: u64,
+    position: LogPosition,
+) -> std::io::Result<u64> {
+    let (mut low, mut high) = (0, entries);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if read_entry(file, mid).await? < position {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+/// The number of whole entries in an offset index file of `len` bytes.
+fn entries_in(len: u64) -> u64 {
+    len.saturating_sub(FILE_HEADER_SIZE + FIRST_SEQ_SIZE) / U64SIZE as u64
+}
+
+async fn count_entries(file: &File) -> std::io::Result<u64> {
+    Ok(entries_in(file.metadata().await?.len()))
+}
+
+fn read_u64_at(file: &std::fs::File, offset: u64) -> std::io::Result<u64> {
+    let mut bytes = [0u8; U64SIZE];
+    file.read_exact_at(&mut bytes, offset)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// Reads the header of the item at `position`, keeping the segment it is in open in `segment`
+/// for the next item. Returns `None` if the header is corrupt.
+async fn read_item_len(
+    segment: &mut Option<(LogPosition, File)>,
+    path: &Path,
+    bases: &[LogPosition],
+    position: LogPosition,
+) -> Result<Option<u32>, OpenError> {
+    let base = bases
+        .iter()
+        .rev()
+        .find(|base| **base <= position)
+        .copied()
+        .unwrap_or(bases[0]);
+    let segment_file = match segment {
+        Some((open_base, segment_file)) if *open_base == base => segment_file,
+        _ => {
+            let segment_file = File::open(segment_path(path, base))
+                .await
+                .map_err(|e| OpenError::Io(Box::new(e)))?;
+            &mut segment.insert((base, segment_file)).1
+        }
+    };
+    segment_file
+        .seek(std::io::SeekFrom::Start(FILE_HEADER_SIZE + position - base))
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    Ok(read_log_item_header(segment_file, position)
+        .await
+        .ok()
+        .map(|(len, _)| len))
+}
+
+/// Adds the positions of the items from `position` up to the tail to the index. Stops at the
+/// first corrupt item header, returning its position.
+async fn index_items(
+    file: &mut File,
+    path: &Path,
+    bases: &[LogPosition],
+    mut position: LogPosition,
+    tail_pos: LogPosition,
+) -> Result<Option<LogPosition>, OpenError> {
+    let mut segment = None;
+    let mut corrupt = None;
+    while position < tail_pos {
+        let len = match read_item_len(&mut segment, path, bases, position).await? {
+            Some(len) => len,
+            None => {
+                corrupt = Some(position);
+                break;
+            }
+        };
+
+        file.write_u64(position)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        position += item_size(len);
+    }
+    file.flush().await.map_err(|e| OpenError::Io(Box::new(e)))?;
+    Ok(corrupt)
+}
+
+/// Maps each item's sequence number to its position, one `u64` per item after the header and
+/// the sequence number of the first entry. Entries for items removed by the retention policy
+/// are dropped, see `remove_before`.
+///
+/// Like the time index this is not synced, when the log is opened entries after the tail are
+/// dropped and any missing entries are added by reading the item headers. If the log had
+/// already removed items when the index was first built, numbering starts from the first item
+/// still available. A corrupt item header stops the rebuild, leaving the items after it
+/// without entries.
+pub(crate) struct OffsetIndexWriter {
+    file: File,
+    first_seq: SequenceNumber,
+    entries: u64,
+}
+
+impl OffsetIndexWriter {
+    /// Opens the index, adding any missing entries. Also returns the position of the corrupt
+    /// item header the entries stopped at, if any.
+    pub(crate) async fn open(
+        path: &Path,
+        bases: &[LogPosition],
+        tail_pos: LogPosition,
+    ) -> Result<(OffsetIndexWriter, Option<LogPosition>), OpenError> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(offset_index_path(path))
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+
+        let mut header = [0u8; (FILE_HEADER_SIZE + FIRST_SEQ_SIZE) as usize];
+        let (first_seq, entries) = match file.read_exact(&mut header).await {
+            Ok(_) => {
+                check_file_version(parse_file_version(&header, &OFFSET_INDEX_MAGIC))?;
+                let mut first_seq = [0u8; U64SIZE];
+                first_seq.copy_from_slice(&header[FILE_HEADER_SIZE as usize..]);
+                let entries = count_entries(&file)
+                    .await
+                    .map_err(|e| OpenError::Io(Box::new(e)))?;
+                let entries = count_before(&mut file, entries, tail_pos)
+                    .await
+                    .map_err(|e| OpenError::Io(Box::new(e)))?;
+                (u64::from_be_bytes(first_seq), entries)
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                file.set_len(0)
+                    .await
+                    .map_err(|e| OpenError::Io(Box::new(e)))?;
+                file.seek(std::io::SeekFrom::Start(0))
+                    .await
+                    .map_err(|e| OpenError::Io(Box::new(e)))?;
+                write_file_header(&mut file, &OFFSET_INDEX_MAGIC).await?;
+                file.write_u64(0)
+                    .await
+                    .map_err(|e| OpenError::Io(Box::new(e)))?;
+                (0, 0)
+            }
+            Err(e) => return Err(OpenError::Io(Box::new(e))),
+        };
+
+        // Find where the entries stop, then add any missing ones
+        let next_pos = if entries == 0 {
+            Ok(bases[0])
+        } else {
+            let last = read_entry(&mut file, entries - 1)
+                .await
+                .map_err(|e| OpenError::Io(Box::new(e)))?;
+            match read_item_len(&mut None, path, bases, last).await? {
+                Some(len) => Ok(last + item_size(len)),
+                None => Err(last),
+            }
+        };
+        file.set_len(entry_offset(entries))
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        file.seek(std::io::SeekFrom::Start(entry_offset(entries)))
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        let corrupt = match next_pos {
+            Ok(next_pos) => index_items(&mut file, path, bases, next_pos, tail_pos).await?,
+            Err(last) => Some(last),
+        };
+
+        let entries = count_entries(&file)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        let writer = OffsetIndexWriter {
+            file,
+            first_seq,
+            entries,
+        };
+        Ok((writer, corrupt))
+    }
+
+    pub(crate) async fn add(&mut self, position: LogPosition) -> std::io::Result<()> {
+        self.file.write_u64(position).await?;
+        self.file.flush().await?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Removes the entries for items at or after `position`, see `SegmentWriter::discard_from`.
+    pub(crate) async fn discard_from(&mut self, position: LogPosition) -> std::io::Result<()> {
+        self.entries = count_before(&mut self.file, self.entries, position).await?;
+        self.file.set_len(entry_offset(self.entries)).await?;
+        self.file
+            .seek(std::io::SeekFrom::Start(entry_offset(self.entries)))
+            .await?;
+        Ok(())
+    }
+
+    /// Drops the entries for the items before `position`, once the retention policy removes the
+    /// segments holding them. The remaining items keep their sequence numbers.
+    pub(crate) async fn remove_before(
+        &mut self,
+        path: &Path,
+        position: LogPosition,
+    ) -> Result<(), OpenError> {
+        let removed = count_before(&mut self.file, self.entries, position)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        if removed == 0 {
+            // Entries are added at the end of the file
+            self.file
+                .seek(std::io::SeekFrom::Start(entry_offset(self.entries)))
+                .await
+                .map_err(|e| OpenError::Io(Box::new(e)))?;
+            return Ok(());
+        }
+        self.file
+            .seek(std::io::SeekFrom::Start(entry_offset(removed)))
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+
+        let first_seq = self.first_seq + removed;
+        let mut contents = first_seq.to_be_bytes().to_vec();
+        self.file
+            .read_to_end(&mut contents)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        self.file = replace_file(&offset_index_path(path), &OFFSET_INDEX_MAGIC, &contents).await?;
+        self.first_seq = first_seq;
+        self.entries -= removed;
+        Ok(())
+    }
+
+    /// The number of items in each of the segments starting at `bases`, including items which
+    /// have not been committed yet.
+    pub(crate) async fn segment_items(
+        &mut self,
+        bases: &[LogPosition],
+    ) -> std::io::Result<Vec<u64>> {
+        let mut starts = Vec::with_capacity(bases.len() + 1);
+        for base in bases {
+            starts.push(count_before(&mut self.file, self.entries, *base).await?);
+        }
+        starts.push(self.entries);
+
+        // Entries are added at the end of the file
+        self.file
+            .seek(std::io::SeekFrom::Start(entry_offset(self.entries)))
+            .await?;
+        Ok(starts.windows(2).map(|items| items[1] - items[0]).collect())
+    }
+}
+
+/// Looks up entries in the offset index for readers, through a read handle shared by every
+/// reader of the log, see `SegmentFiles`. The handle is opened again once the index has been
+/// replaced by `OffsetIndexWriter::remove_before`.
+pub(crate) struct OffsetIndex {
+    file: Arc<std::fs::File>,
+}
+
+impl OffsetIndex {
+    pub(crate) fn new(file: Arc<std::fs::File>) -> OffsetIndex {
+        OffsetIndex { file }
+    }
+
+    /// Runs a lookup on the blocking pool, passing it the sequence number of the first entry and
+    /// the number of entries in the index.
+    async fn lookup<T: Send + 'static>(
+        &self,
+        lookup: impl FnOnce(&std::fs::File, SequenceNumber, u64) -> std::io::Result<T> + Send + 'static,
+    ) -> std::io::Result<T> {
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || {
+            let first_seq = read_u64_at(&file, FILE_HEADER_SIZE)?;
+            let entries = entries_in(file.metadata()?.len());
+            lookup(&file, first_seq, entries)
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+
+    /// The sequence number of the item at `position`, or of the next item after it. Entries
+    /// after the tail have not been committed and are ignored by passing the tail.
+    pub(crate) async fn seq_for(&self, position: LogPosition) -> std::io::Result<u64> {
+        self.lookup(move |file, first_seq, entries| {
+            let (mut low, mut high) = (0, entries);
+            while low < high {
+                let mid = low + (high - low) / 2;
+                if read_u64_at(file, entry_offset(mid))? < position {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            Ok(first_seq + low)
+        })
+        .await
+    }
+
+    /// The position of an item, which might not have been committed yet. Items removed by the
+    /// retention policy have no position.
+    pub(crate) async fn position(
+        &self,
+        seq: SequenceNumber,
+    ) -> std::io::Result<Option<LogPosition>> {
+        self.lookup(move |file, first_seq, entries| {
+            match seq.checked_sub(first_seq).filter(|entry| *entry < entries) {
+                Some(entry) => read_u64_at(file, entry_offset(entry)).map(Some),
+                None => Ok(None),
+            }
+        })
+        .await
+    }
+}
@@ -1,14 +1,118 @@
+use std::convert::TryInto;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::time::Instant;
 
+use crate::format::{
+    check_file_version, parse_file_version, write_file_header, FILE_HEADER_SIZE, LOG_MAGIC,
+    TAIL_MAGIC,
+};
+use crate::segment::{create_segment_file, list_segments, segment_path};
+use crate::verify::verify_segment;
 use crate::{LogPosition, OpenError, U64SIZE};
 
-async fn read_log_position(tail_file: &mut File) -> Result<LogPosition, OpenError> {
+/// A generation, the tail position and a CRC32C of both.
+pub(crate) const TAIL_RECORD_SIZE: u64 = 8 + 8 + 4;
+
+/// The size of a tail file, which has two slots for tail records after its header.
+pub(crate) const TAIL_FILE_SIZE: u64 = FILE_HEADER_SIZE + 2 * TAIL_RECORD_SIZE;
+
+/// Tail files written before file headers were introduced only contain the three positions.
+pub(crate) fn is_headerless_tail_file(contents: &[u8]) -> bool {
+    contents.len() == U64SIZE * 3 && parse_file_version(contents, &TAIL_MAGIC).is_none()
+}
+
+/// The tail position as committed by one write of the tail file.
+///
+/// Each write increments the generation and goes to the slot the previous write did not use,
+/// so a torn write can only damage the newest record and the one before it is still valid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TailRecord {
+    pub generation: u64,
+    pub position: LogPosition,
+}
+
+impl TailRecord {
+    /// The offset of the slot this record is written to.
+    pub fn slot_offset(&self) -> u64 {
+        FILE_HEADER_SIZE + (self.generation % 2) * TAIL_RECORD_SIZE
+    }
+
+    pub fn encode(&self) -> [u8; TAIL_RECORD_SIZE as usize] {
+        let mut bytes = [0u8; TAIL_RECORD_SIZE as usize];
+        bytes[..8].copy_from_slice(&self.generation.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.position.to_be_bytes());
+        let crc = crc32c::crc32c(&bytes[..16]);
+        bytes[16..].copy_from_slice(&crc.to_be_bytes());
+        bytes
+    }
+
+    /// Returns `None` if the record fails its checksum.
+    pub fn decode(bytes: &[u8]) -> Option<TailRecord> {
+        let bytes: &[u8; TAIL_RECORD_SIZE as usize] = bytes.try_into().ok()?;
+        let crc = u32::from_be_bytes([bytes[16], bytes[17], bytes[18], bytes[19]]);
+        if crc32c::crc32c(&bytes[..16]) != crc {
+            return None;
+        }
+        let mut generation = [0u8; 8];
+        generation.copy_from_slice(&bytes[..8]);
+        let mut position = [0u8; 8];
+        position.copy_from_slice(&bytes[8..16]);
+        Some(TailRecord {
+            generation: u64::from_be_bytes(generation),
+            position: u64::from_be_bytes(position),
+        })
+    }
+}
+
+/// Parses both slots of a tail file, either of which may be damaged.
+pub(crate) fn parse_tail_records(contents: &[u8]) -> Result<[Option<TailRecord>; 2], OpenError> {
+    if contents.len() as u64 != TAIL_FILE_SIZE {
+        return Err(OpenError::CorruptTailPosition);
+    }
+    let mut slots = contents[FILE_HEADER_SIZE as usize..].chunks_exact(TAIL_RECORD_SIZE as usize);
+    let first = slots.next().and_then(TailRecord::decode);
+    let second = slots.next().and_then(TailRecord::decode);
+    Ok([first, second])
+}
+
+/// Reads the newest valid tail record from the contents of a tail file.
+pub(crate) fn read_log_position(contents: &[u8]) -> Result<TailRecord, OpenError> {
+    parse_tail_records(contents)?
+        .iter()
+        .flatten()
+        .max_by_key(|record| record.generation)
+        .copied()
+        .ok_or(OpenError::CorruptTailPosition)
+}
+
+/// Reads the tail record without opening the tail file for writing or locking it, so the log
+/// can be open for writing elsewhere.
+pub(crate) async fn read_tail_file(path: &Path) -> Result<TailRecord, OpenError> {
+    let contents = tokio::fs::read(path.join("tail"))
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    if is_headerless_tail_file(&contents) {
+        return Err(OpenError::UnsupportedVersion(0));
+    }
+    if (contents.len() as u64) < FILE_HEADER_SIZE {
+        return Err(OpenError::CorruptTailPosition);
+    }
+    check_file_version(parse_file_version(&contents, &TAIL_MAGIC))?;
+    read_log_position(&contents)
+}
+
+/// Reads the three copies of the tail position used before tail records had checksums.
+pub(crate) async fn read_copied_log_position(
+    tail_file: &mut File,
+    offset: u64,
+) -> Result<LogPosition, OpenError> {
     tail_file
-        .seek(tokio::io::SeekFrom::Start(0))
+        .seek(tokio::io::SeekFrom::Start(offset))
         .await
         .map_err(|e| OpenError::Io(Box::new(e)))?;
     let first_pos = tail_file
@@ -32,7 +136,112 @@ async fn read_log_position(tail_file: &mut File) -> Result<LogPosition, OpenErro
     }
 }
 
-pub async fn open_tail_file(path: &Path) -> Result<(tokio::fs::File, LogPosition), OpenError> {
+/// Finds the tail by reading the items in the last segment, stopping at the first which is
+/// incomplete or corrupt. Items never span segments, so earlier segments do not need reading.
+async fn rebuild_tail_position(path: &Path) -> Result<LogPosition, OpenError> {
+    let bases = list_segments(path)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    let base = match bases.last() {
+        Some(base) => *base,
+        None => return Ok(0),
+    };
+
+    let mut segment_file = File::open(segment_path(path, base))
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    let segment_file_size = segment_file
+        .metadata()
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?
+        .len();
+    if segment_file_size < FILE_HEADER_SIZE {
+        // The segment was being started, `open_segment_file` creates it again
+        return Ok(base);
+    }
+    let mut header = [0u8; FILE_HEADER_SIZE as usize];
+    segment_file
+        .read_exact(&mut header)
+        .await
+        .map_err(|_| OpenError::InvalidFileHeader)?;
+    check_file_version(parse_file_version(&header, &LOG_MAGIC))?;
+
+    let end = base + segment_file_size - FILE_HEADER_SIZE;
+    let (_, first_bad) = verify_segment(segment_file, base, end).await;
+    Ok(first_bad.map_or(end, |(position, _)| position))
+}
+
+/// Replaces the contents of the tail file with a fresh header and `position` in both slots.
+pub(crate) async fn write_tail_file(
+    tail_file: &mut File,
+    position: LogPosition,
+) -> Result<TailRecord, OpenError> {
+    tail_file
+        .set_len(0)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    tail_file
+        .seek(tokio::io::SeekFrom::Start(0))
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    write_file_header(tail_file, &TAIL_MAGIC).await?;
+
+    let record = TailRecord {
+        generation: 0,
+        position,
+    };
+    for _ in 0usize..2 {
+        tail_file
+            .write_all(&record.encode())
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+    }
+    tail_file
+        .sync_all()
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    Ok(record)
+}
+
+/// How often a lock held by someone else is tried again while waiting for it.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Takes an exclusive lock on a file, waiting up to `timeout` for it to be released if it is
+/// already held. Fails with `OpenError::AlreadyOpen` if it is still held.
+///
+/// This is how logs are locked, and can be used to lock other files the same way.
+pub async fn lock_file_with_timeout(
+    file: &impl AsRawFd,
+    timeout: Option<Duration>,
+) -> Result<(), OpenError> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        match nix::fcntl::flock(
+            file.as_raw_fd(),
+            nix::fcntl::FlockArg::LockExclusiveNonblock,
+        ) {
+            Ok(()) => return Ok(()),
+            Err(nix::Error::Sys(nix::errno::Errno::EAGAIN)) => match deadline {
+                Some(deadline) if Instant::now() < deadline => {
+                    tokio::time::sleep(LOCK_RETRY_INTERVAL.min(deadline - Instant::now())).await
+                }
+                _ => return Err(OpenError::AlreadyOpen),
+            },
+            Err(e) => return Err(OpenError::Io(Box::new(e))),
+        }
+    }
+}
+
+/// Opens and locks the tail file, returning the newest tail record and whether it had to be
+/// rebuilt.
+///
+/// If the tail file was lost or neither of its records is valid, the position is found by
+/// reading the items when `rebuild_tail` is set, otherwise the log cannot be opened.
+pub async fn open_tail_file(
+    path: &Path,
+    rebuild_tail: bool,
+    lock_timeout: Option<Duration>,
+) -> Result<(tokio::fs::File, TailRecord, bool), OpenError> {
     let tail_file_path = PathBuf::from(path).join("tail");
     tokio::fs::create_dir_all(
         tail_file_path
@@ -43,17 +252,14 @@ pub async fn open_tail_file(path: &Path) -> Result<(tokio::fs::File, LogPosition
     .map_err(|e| OpenError::Io(Box::new(e)))?;
     let mut tail_file = tokio::fs::OpenOptions::new()
         .create(true)
+        .truncate(false)
         .read(true)
         .write(true)
         .open(tail_file_path)
         .await
         .map_err(|e| OpenError::Io(Box::new(e)))?;
 
-    nix::fcntl::flock(
-        tail_file.as_raw_fd(),
-        nix::fcntl::FlockArg::LockExclusiveNonblock,
-    )
-    .map_err(|_| OpenError::AlreadyOpen)?;
+    lock_file_with_timeout(&tail_file, lock_timeout).await?;
 
     let mut contents = Vec::new();
     tail_file
@@ -61,52 +267,126 @@ pub async fn open_tail_file(path: &Path) -> Result<(tokio::fs::File, LogPosition
         .await
         .map_err(|e| OpenError::Io(Box::new(e)))?;
 
-    let position: LogPosition = if contents.is_empty() {
-        let zero = 0u64;
-
-        for _ in 0usize..3 {
-            tail_file
-                .write_u64(zero)
-                .await
-                .map_err(|e| OpenError::Io(Box::new(e)))?;
-        }
-
-        zero
-    } else if contents.len() == U64SIZE * 3 {
-        read_log_position(&mut tail_file).await?
+    let has_segments = !list_segments(path)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?
+        .is_empty();
+    let record = if contents.is_empty() && !has_segments {
+        let record = write_tail_file(&mut tail_file, 0).await?;
+        return Ok((tail_file, record, false));
+    } else if is_headerless_tail_file(&contents) {
+        return Err(OpenError::UnsupportedVersion(0));
+    } else if contents.len() as u64 >= FILE_HEADER_SIZE {
+        // Older versions must be migrated rather than rebuilt
+        check_file_version(parse_file_version(&contents, &TAIL_MAGIC))?;
+        read_log_position(&contents)
     } else {
-        return Err(OpenError::CorruptTailPosition);
+        // The tail file was lost
+        Err(OpenError::CorruptTailPosition)
     };
-    Ok((tail_file, position))
+
+    match record {
+        Ok(record) => Ok((tail_file, record, false)),
+        Err(OpenError::CorruptTailPosition) if rebuild_tail => {
+            let position = rebuild_tail_position(path).await?;
+            let record = write_tail_file(&mut tail_file, position).await?;
+            Ok((tail_file, record, true))
+        }
+        Err(e) => Err(e),
+    }
 }
 
-pub async fn open_log_file(
+/// Opens the last segment of the log for writing, checking it holds everything up to the tail.
+///
+/// Anything written after the tail was never committed, it is truncated and the number of bytes
+/// discarded is returned. If the segment starts at the tail and a crash left it without a whole
+/// file header it is created again, which is returned as `true`.
+pub async fn open_segment_file(
     path: &Path,
+    base: LogPosition,
     expected_tail_pos: LogPosition,
-) -> Result<(tokio::fs::File, bool), OpenError> {
-    let log_file_path = PathBuf::from(path).join("log");
-    tokio::fs::create_dir_all(
-        log_file_path
-            .parent()
-            .expect("Could not find parent directory"),
-    )
-    .await
-    .map_err(|e| OpenError::Io(Box::new(e)))?;
+) -> Result<(tokio::fs::File, u64, bool), OpenError> {
     let mut log_file = tokio::fs::OpenOptions::new()
+        .read(true)
         .write(true)
-        .create(true)
-        .open(log_file_path)
+        .open(segment_path(path, base))
         .await
         .map_err(|e| OpenError::Io(Box::new(e)))?;
 
-    let actual_tail_pos = log_file
+    let log_file_size = log_file
         .seek(tokio::io::SeekFrom::End(0))
         .await
         .map_err(|e| OpenError::Io(Box::new(e)))?;
+    if log_file_size < FILE_HEADER_SIZE && base == expected_tail_pos {
+        let log_file = create_segment_file(path, base).await?;
+        return Ok((log_file, 0, true));
+    }
+
+    let mut header = [0u8; FILE_HEADER_SIZE as usize];
+    log_file
+        .seek(tokio::io::SeekFrom::Start(0))
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    log_file
+        .read_exact(&mut header)
+        .await
+        .map_err(|_| OpenError::InvalidFileHeader)?;
+    check_file_version(parse_file_version(&header, &LOG_MAGIC))?;
+
+    let actual_tail_pos = base + log_file_size.saturating_sub(FILE_HEADER_SIZE);
 
     if actual_tail_pos < expected_tail_pos {
         return Err(OpenError::LogTooSmall);
     }
 
-    Ok((log_file, actual_tail_pos > expected_tail_pos))
+    let discarded = actual_tail_pos - expected_tail_pos;
+    if discarded > 0 {
+        log_file
+            .set_len(FILE_HEADER_SIZE + expected_tail_pos - base)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        log_file
+            .sync_all()
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+    }
+
+    Ok((log_file, discarded, false))
+}
+
+/// Finds the segments making up the log, creating the first if the log is new.
+///
+/// Segments starting after the tail were never committed and are removed, the number of bytes
+/// they held is returned with the segments.
+pub async fn open_segments(
+    path: &Path,
+    tail_pos: LogPosition,
+) -> Result<(Vec<LogPosition>, u64), OpenError> {
+    let mut bases = list_segments(path)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+
+    let mut discarded = 0;
+    for base in bases.iter().filter(|base| **base > tail_pos) {
+        let stale_path = segment_path(path, *base);
+        let len = tokio::fs::metadata(&stale_path)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?
+            .len();
+        tokio::fs::remove_file(&stale_path)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        discarded += len.saturating_sub(FILE_HEADER_SIZE);
+    }
+    bases.retain(|base| *base <= tail_pos);
+
+    if bases.is_empty() {
+        if tail_pos > 0 {
+            return Err(OpenError::LogTooSmall);
+        }
+        create_segment_file(path, 0).await?;
+        bases.push(0);
+    }
+
+    Ok((bases, discarded))
 }
@@ -1,20 +1,44 @@
+use std::future::Future;
+use std::ops::Range;
 use std::path::Path;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use std::time::SystemTime;
 
-use tokio::fs::File;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
-use tokio::io::{ReadBuf, SeekFrom};
+use bytes::Bytes;
+use futures::Stream;
+use tokio::io::ReadBuf;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::task::JoinHandle;
 
-use crate::checksum::calculate;
-use crate::LogPosition;
+use crate::checksum::{calculate, update_payload, PayloadChecksum};
+use crate::format::FILE_HEADER_SIZE;
+use crate::metadata::Metadata;
+use crate::offsetindex::SequenceNumber;
+use crate::recover::find_next_item;
+use crate::segment::{read_exact_at, spawn_read_at, SegmentFiles, Segments};
+use crate::timeindex::{find_position_before, from_timestamp, to_timestamp, Timestamp};
+use crate::{
+    item_size, LogPosition, ITEM_HEADER_SIZE, ITEM_TRAILER_SIZE, METADATA_LEN_SIZE, TIMESTAMP_SIZE,
+};
 
 #[derive(Debug)]
 pub enum Error {
     Io(Box<dyn std::error::Error + Sync + Send>),
-    /// Indicates that a read position was not aligned to an item, or the log is corrupt.
+    /// Indicates that an item header is corrupt.
     InvalidItemChecksum,
+    /// The position is not the start of an item, or is after the tail.
+    MisalignedPosition,
+    /// Indicates that the contents of an item did not match the checksum written with it.
+    InvalidPayloadChecksum,
+    /// Indicates that the metadata of an item could not be read, the log is corrupt.
+    InvalidMetadata,
+    /// The position was removed by the retention policy, the first available position is given.
+    PositionRemoved(LogPosition),
+    /// The sequence number is after the end of the log, the number of items is given.
+    SequenceNotFound(u64),
 }
 
 impl std::fmt::Display for Error {
@@ -22,24 +46,52 @@ impl std::fmt::Display for Error {
         match self {
             Error::Io(e) => e.fmt(f),
             Error::InvalidItemChecksum => write!(f, "Item checksum failed"),
+            Error::MisalignedPosition => write!(f, "Position is not the start of an item"),
+            Error::InvalidPayloadChecksum => write!(f, "Item contents checksum failed"),
+            Error::InvalidMetadata => write!(f, "Invalid item metadata"),
+            Error::PositionRemoved(first) => {
+                write!(f, "Position was removed, the first available is {}", first)
+            }
+            Error::SequenceNotFound(count) => {
+                write!(f, "Sequence number not found, the log has {} items", count)
+            }
         }
     }
 }
 
 impl std::error::Error for Error {}
 
+/// How much of an item's contents is read at a time.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
 pub struct ReaderFactory {
     pub(crate) path: Box<Path>,
+    pub(crate) segments: Arc<Segments>,
     pub(crate) tail_recv: tokio::sync::watch::Receiver<LogPosition>,
+    files: Arc<SegmentFiles>,
 }
 
 struct ReaderState {
+    path: Box<Path>,
+    segments: Arc<Segments>,
+    files: Arc<SegmentFiles>,
     tail_recv: tokio::sync::watch::Receiver<LogPosition>,
     pos: LogPosition,
+    /// The sequence number of the item at `pos`.
+    seq: SequenceNumber,
+}
+
+impl ReaderState {
+    /// The shared read handle for the segment starting at `base`.
+    async fn segment_file(&self, base: LogPosition) -> Result<Arc<std::fs::File>, Error> {
+        self.files
+            .get(base)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))
+    }
 }
 
 pub struct Reader {
-    file: File,
     state: ReaderState,
 }
 
@@ -47,13 +99,29 @@ impl Reader {
     pub fn position(&self) -> LogPosition {
         self.state.pos
     }
+
+    /// The sequence number of the next item.
+    pub fn seq(&self) -> SequenceNumber {
+        self.state.seq
+    }
 }
 
 pub struct LogItem {
     start_pos: LogPosition,
+    seq: SequenceNumber,
     len: u32,
+    timestamp: Timestamp,
+    metadata: Metadata,
     read: usize,
-    file: File,
+    file: Arc<std::fs::File>,
+    /// Where the contents start in the segment file.
+    contents_offset: u64,
+    /// The number of bytes of the contents read from the file, some might still be buffered.
+    fetched: usize,
+    buffered: Bytes,
+    pending: Option<JoinHandle<std::io::Result<Vec<u8>>>>,
+    expected_checksum: PayloadChecksum,
+    checksum: PayloadChecksum,
 
     reader_state: ReaderState,
 }
@@ -63,11 +131,27 @@ impl LogItem {
         self.start_pos
     }
 
+    /// The number of items appended before this one.
+    pub fn seq(&self) -> SequenceNumber {
+        self.seq
+    }
+
     /// Returns the length of the contents in bytes.
     pub fn len(&self) -> u32 {
         self.len
     }
 
+    /// When the item was appended. Items migrated from logs without timestamps are given the
+    /// Unix epoch.
+    pub fn timestamp(&self) -> SystemTime {
+        from_timestamp(self.timestamp)
+    }
+
+    /// The metadata appended with the item, empty if there was none.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
     pub fn left_to_read(&self) -> usize {
         self.len as usize - self.read
     }
@@ -79,61 +163,372 @@ impl LogItem {
     /// Finish with the log item so we can recover the reader to fetch the next.
     pub fn finish(self) -> Reader {
         Reader {
-            file: self.file,
             state: self.reader_state,
         }
     }
 }
 
-impl<'a> AsyncRead for LogItem {
+impl AsyncRead for LogItem {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        // There must be a better way to do this?
+        let item = &mut *self;
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        let unfetched = item.len as usize - item.fetched;
+        if item.buffered.is_empty() && unfetched > 0 {
+            if item.pending.is_none() {
+                item.pending = Some(spawn_read_at(
+                    item.file.clone(),
+                    item.contents_offset + item.fetched as u64,
+                    unfetched.min(READ_CHUNK_SIZE),
+                ));
+            }
+            let pending = item.pending.as_mut().expect("Read to be pending");
+            let result = futures::ready!(Pin::new(pending).poll(cx));
+            item.pending = None;
+            let bytes = result.map_err(std::io::Error::other)??;
+
+            // The segment was truncated after the item was written, so the contents can't be
+            // verified
+            if bytes.is_empty() {
+                return Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()));
+            }
+            item.fetched += bytes.len();
+            item.buffered = Bytes::from(bytes);
+        }
+
         let length_before = buf.filled().len();
-        let left_to_read = self.left_to_read();
-        let mut limited_file = (&mut self.file).take(left_to_read as u64);
-        let pinned_file = Pin::new(&mut limited_file);
-        let res = pinned_file.poll_read(cx, buf);
-        let length_after = buf.filled().len();
-        self.read += length_after - length_before;
-        res
+        let chunk = item
+            .buffered
+            .split_to(item.buffered.len().min(buf.remaining()));
+        buf.put_slice(&chunk);
+        item.read += chunk.len();
+        item.checksum = update_payload(item.checksum, &chunk);
+
+        // The contents can only be verified once the whole item has been read
+        if item.left_to_read() == 0 && item.checksum != item.expected_checksum {
+            buf.set_filled(length_before);
+            return Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                Error::InvalidPayloadChecksum,
+            )));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl ReaderFactory {
+    pub(crate) fn new(
+        path: Box<Path>,
+        segments: Arc<Segments>,
+        tail_recv: tokio::sync::watch::Receiver<LogPosition>,
+    ) -> ReaderFactory {
+        let files = Arc::new(SegmentFiles::new(path.clone(), segments.clone()));
+        ReaderFactory {
+            path,
+            segments,
+            tail_recv,
+            files,
+        }
     }
 }
 
 impl ReaderFactory {
+    /// The position of the oldest item which has not been removed by the retention policy.
+    pub fn first_position(&self) -> LogPosition {
+        self.segments.first()
+    }
+
+    /// The position the next item will be appended at, reading from here only sees new items.
+    pub fn tail_position(&self) -> LogPosition {
+        *self.tail_recv.borrow()
+    }
+
+    /// Starts reading from the item at `position`, which must be the position of an item or the
+    /// tail. Positions are checked against the offset index.
     pub async fn read_from(&self, position: LogPosition) -> Result<Reader, Error> {
-        let path = self.path.join("log");
-        let file = tokio::fs::OpenOptions::new()
-            .read(true)
-            .open(&path)
+        let tail_pos = *self.tail_recv.borrow();
+        let first = self.segments.first();
+        if position < first {
+            return Err(Error::PositionRemoved(first));
+        }
+
+        let offset_index = self
+            .files
+            .offset_index()
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        let seq = offset_index
+            .seq_for(position)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        let aligned = position == tail_pos
+            || (position < tail_pos
+                && offset_index
+                    .position(seq)
+                    .await
+                    .map_err(|e| Error::Io(Box::new(e)))?
+                    == Some(position));
+        if !aligned {
+            return Err(Error::MisalignedPosition);
+        }
+
+        self.read_from_seq(position, seq).await
+    }
+
+    /// Starts reading backwards from the item before `position`, which must be the position of an
+    /// item or the tail. Items are returned newest first, ending at the first item not removed by
+    /// the retention policy.
+    pub async fn read_backwards_from(
+        &self,
+        position: LogPosition,
+    ) -> Result<BackwardReader, Error> {
+        let reader = self.read_from(position).await?;
+        Ok(BackwardReader {
+            state: reader.state,
+        })
+    }
+
+    /// Starts reading backwards from the last item appended.
+    pub async fn read_backwards_from_tail(&self) -> Result<BackwardReader, Error> {
+        self.read_backwards_from(self.tail_position()).await
+    }
+
+    /// Starts reading from the item `count` items before the tail, so at most the last `count`
+    /// items are read before reaching the tail. Starts from the first item still available if
+    /// there are fewer, and from the tail if `count` is zero.
+    pub async fn read_from_last(&self, count: u64) -> Result<Reader, Error> {
+        let tail_pos = self.tail_position();
+        let first = self.segments.first();
+        let offset_index = self
+            .files
+            .offset_index()
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
+        let tail_seq = offset_index
+            .seq_for(tail_pos)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        let first_seq = offset_index
+            .seq_for(first)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        let seq = tail_seq.saturating_sub(count).max(first_seq);
+        let position = if seq == tail_seq {
+            tail_pos
+        } else {
+            offset_index
+                .position(seq)
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?
+                .ok_or(Error::SequenceNotFound(tail_seq))?
+        };
+        self.read_from_seq(position, seq).await
+    }
+
+    /// Starts reading from the item with the given sequence number, or from the tail if it is
+    /// the number of items in the log.
+    pub async fn read_from_index(&self, seq: SequenceNumber) -> Result<Reader, Error> {
+        let tail_pos = *self.tail_recv.borrow();
+        let offset_index = self
+            .files
+            .offset_index()
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        let position = offset_index
+            .position(seq)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?
+            .filter(|position| *position < tail_pos);
+
+        match position {
+            Some(position) => self.read_from_seq(position, seq).await,
+            None => {
+                let first = self.segments.first();
+                if seq
+                    < offset_index
+                        .seq_for(first)
+                        .await
+                        .map_err(|e| Error::Io(Box::new(e)))?
+                {
+                    return Err(Error::PositionRemoved(first));
+                }
+
+                let count = offset_index
+                    .seq_for(tail_pos)
+                    .await
+                    .map_err(|e| Error::Io(Box::new(e)))?;
+                if seq == count {
+                    self.read_from_seq(tail_pos, seq).await
+                } else {
+                    Err(Error::SequenceNotFound(count))
+                }
+            }
+        }
+    }
+
+    /// The number of items appended to the log, including any removed by the retention policy.
+    pub async fn item_count(&self) -> Result<u64, Error> {
+        let tail_pos = *self.tail_recv.borrow();
+        self.files
+            .offset_index()
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?
+            .seq_for(tail_pos)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))
+    }
+
+    async fn read_from_seq(
+        &self,
+        position: LogPosition,
+        seq: SequenceNumber,
+    ) -> Result<Reader, Error> {
+        let first = self.segments.first();
+        if position < first {
+            return Err(Error::PositionRemoved(first));
+        }
+
         let tail_recv = self.tail_recv.clone();
 
         Ok(Reader {
-            file,
             state: ReaderState {
+                path: self.path.clone(),
+                segments: self.segments.clone(),
+                files: self.files.clone(),
                 tail_recv,
                 pos: position,
+                seq,
             },
         })
     }
+
+    /// Starts reading from the first item appended at or after `time`, or from the tail if
+    /// there is none yet.
+    pub async fn read_from_time(&self, time: SystemTime) -> Result<Reader, Error> {
+        let timestamp = to_timestamp(time);
+        let tail_pos = *self.tail_recv.borrow();
+        let first = self.segments.first();
+        let indexed = find_position_before(&self.path, timestamp, tail_pos)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+
+        // The index is sparse, so read forward from the last indexed item before the time
+        let mut position = indexed.unwrap_or(first).max(first);
+        while position < tail_pos {
+            let base = self.segments.base_for(position);
+            let file = self
+                .files
+                .get(base)
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+            let bytes = read_exact_at(
+                file,
+                FILE_HEADER_SIZE + position - base,
+                (ITEM_HEADER_SIZE + TIMESTAMP_SIZE) as usize,
+            )
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+            let mut bytes = &bytes[..];
+            let (record_len, _) = read_log_item_header(&mut bytes, position).await?;
+            let item_timestamp = bytes.read_u64().await.map_err(|e| Error::Io(Box::new(e)))?;
+            if item_timestamp >= timestamp {
+                break;
+            }
+            position += item_size(record_len);
+        }
+
+        self.read_from(position).await
+    }
 }
 
-async fn read_log_item_size(file: &mut File, position: LogPosition) -> Result<u32, Error> {
+pub(crate) async fn read_log_item_header<R: AsyncRead + Unpin>(
+    file: &mut R,
+    position: LogPosition,
+) -> Result<(u32, PayloadChecksum), Error> {
     let checksum = file.read_u16().await.map_err(|e| Error::Io(Box::new(e)))?;
     let len = file.read_u32().await.map_err(|e| Error::Io(Box::new(e)))?;
+    let payload_checksum = file.read_u32().await.map_err(|e| Error::Io(Box::new(e)))?;
 
     if calculate(position, len) == checksum {
-        Ok(len)
+        Ok((len, payload_checksum))
     } else {
         Err(Error::InvalidItemChecksum)
     }
 }
 
+/// The part of a record before the contents.
+pub(crate) struct RecordStart {
+    pub timestamp: Timestamp,
+    pub metadata: Metadata,
+    /// The length in bytes of the timestamp and metadata.
+    pub len: u32,
+}
+
+/// Reads the start of a record, returning it with the checksum of the bytes read.
+pub(crate) async fn read_record_start<R: AsyncRead + Unpin>(
+    file: &mut R,
+    record_len: u32,
+) -> Result<(RecordStart, PayloadChecksum), Error> {
+    let timestamp = file.read_u64().await.map_err(|e| Error::Io(Box::new(e)))?;
+    let metadata_len = file.read_u16().await.map_err(|e| Error::Io(Box::new(e)))?;
+    let len = TIMESTAMP_SIZE + METADATA_LEN_SIZE + metadata_len as u64;
+    if len > record_len as u64 {
+        return Err(Error::InvalidMetadata);
+    }
+
+    let mut bytes = vec![0u8; metadata_len as usize];
+    file.read_exact(&mut bytes)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+    let mut checksum = update_payload(0, &timestamp.to_be_bytes());
+    checksum = update_payload(checksum, &metadata_len.to_be_bytes());
+    checksum = update_payload(checksum, &bytes);
+    let metadata = Metadata::decode(&bytes).ok_or(Error::InvalidMetadata)?;
+
+    Ok((
+        RecordStart {
+            timestamp,
+            metadata,
+            len: len as u32,
+        },
+        checksum,
+    ))
+}
+
+/// Reads an item's header and the start of its record with positional reads, returning the
+/// record length, the contents checksum, the record start and the checksum of the bytes read.
+async fn read_item_start(
+    file: &Arc<std::fs::File>,
+    position: LogPosition,
+    offset: u64,
+) -> Result<(u32, PayloadChecksum, RecordStart, PayloadChecksum), Error> {
+    let start_len = ITEM_HEADER_SIZE + TIMESTAMP_SIZE + METADATA_LEN_SIZE;
+    let mut bytes = read_exact_at(file.clone(), offset, start_len as usize)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+    let (record_len, expected_checksum) = read_log_item_header(&mut &bytes[..], position).await?;
+
+    let metadata_len = u16::from_be_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]);
+    if TIMESTAMP_SIZE + METADATA_LEN_SIZE + metadata_len as u64 > record_len as u64 {
+        return Err(Error::InvalidMetadata);
+    }
+    if metadata_len > 0 {
+        let metadata = read_exact_at(file.clone(), offset + start_len, metadata_len as usize)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        bytes.extend_from_slice(&metadata);
+    }
+
+    let (record_start, checksum) =
+        read_record_start(&mut &bytes[ITEM_HEADER_SIZE as usize..], record_len).await?;
+    Ok((record_len, expected_checksum, record_start, checksum))
+}
+
 pub enum NextItem {
     Item(LogItem),
     End(Reader),
@@ -155,31 +550,170 @@ impl NextItem {
     }
 }
 
+/// When a stream from `Reader::into_stream`, or reading with `Reader::next_with_options`, ends.
+#[derive(Debug, Clone, Default)]
+pub struct StreamOptions {
+    /// Stop after this many items.
+    pub max_items: Option<usize>,
+    /// Stop before the item at this position.
+    pub end_before: Option<LogPosition>,
+    /// Stop before the item with this sequence number.
+    pub end_before_seq: Option<SequenceNumber>,
+    /// Stop after the item at this position.
+    pub end_after: Option<LogPosition>,
+    /// Wait for more items at the tail instead of ending the stream.
+    pub wait_for_more: bool,
+}
+
+/// An item read in full by the stream from `Reader::into_stream`.
+#[derive(Debug, Clone)]
+pub struct StreamItem {
+    pub position: LogPosition,
+    /// The number of items appended before this one.
+    pub seq: SequenceNumber,
+    /// When the item was appended.
+    pub timestamp: SystemTime,
+    pub metadata: Metadata,
+    pub contents: Bytes,
+}
+
+/// Reads items from the end of each item to its start, using the trailer written after it.
+pub struct BackwardReader {
+    /// Positioned at the end of the next item to read.
+    state: ReaderState,
+}
+
+impl BackwardReader {
+    /// The position the next item read ends at.
+    pub fn position(&self) -> LogPosition {
+        self.state.pos
+    }
+
+    /// Reads the item before the reader's position, or returns `None` once the first item still
+    /// available has been read.
+    pub async fn next(&mut self) -> Result<Option<StreamItem>, Error> {
+        let state = &mut self.state;
+        let first = state.segments.first();
+        if state.pos <= first {
+            return Ok(None);
+        }
+
+        // The item ends at the position, so is in the segment holding the byte before it
+        let base = state.segments.base_for(state.pos - 1);
+        let file = state.segment_file(base).await?;
+
+        let trailer = read_exact_at(
+            file.clone(),
+            FILE_HEADER_SIZE + state.pos - ITEM_TRAILER_SIZE - base,
+            ITEM_TRAILER_SIZE as usize,
+        )
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+        let checksum = u16::from_be_bytes([trailer[0], trailer[1]]);
+        let trailer_len = u32::from_be_bytes([trailer[2], trailer[3], trailer[4], trailer[5]]);
+        let start_pos = match state.pos.checked_sub(item_size(trailer_len)) {
+            Some(start_pos)
+                if start_pos >= base && calculate(start_pos, trailer_len) == checksum =>
+            {
+                start_pos
+            }
+            _ => return Err(Error::InvalidItemChecksum),
+        };
+
+        let offset = FILE_HEADER_SIZE + start_pos - base;
+        let (record_len, expected_checksum, record_start, checksum) =
+            read_item_start(&file, start_pos, offset).await?;
+        if record_len != trailer_len {
+            return Err(Error::InvalidItemChecksum);
+        }
+        let contents_len = record_len - record_start.len;
+        let contents = read_exact_at(
+            file,
+            offset + ITEM_HEADER_SIZE + record_start.len as u64,
+            contents_len as usize,
+        )
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+        if update_payload(checksum, &contents) != expected_checksum {
+            return Err(Error::InvalidPayloadChecksum);
+        }
+
+        state.pos = start_pos;
+        state.seq -= 1;
+        Ok(Some(StreamItem {
+            position: start_pos,
+            seq: state.seq,
+            timestamp: from_timestamp(record_start.timestamp),
+            metadata: record_start.metadata,
+            contents: Bytes::from(contents),
+        }))
+    }
+
+    /// Reads the items as a stream, newest first, stopping after `max_items` if given.
+    pub fn into_stream(
+        self,
+        max_items: Option<usize>,
+    ) -> impl Stream<Item = Result<StreamItem, Error>> {
+        futures::stream::try_unfold((self, max_items), |(mut reader, max_items)| async move {
+            if max_items == Some(0) {
+                return Ok(None);
+            }
+            let item = reader.next().await?;
+            Ok(item.map(|item| (item, (reader, max_items.map(|max_items| max_items - 1)))))
+        })
+    }
+}
+
+/// The result of `Reader::next_recovering`.
+pub enum Recovered {
+    Item(LogItem),
+    /// The items in this range were corrupt and have been skipped, the reader continues from the
+    /// end of the range.
+    Skipped(Range<LogPosition>, Reader),
+    End(Reader),
+}
+
 impl Reader {
     async fn read_item(self) -> Result<LogItem, Error> {
-        let Reader { mut file, state } = self;
+        let Reader { state } = self;
 
-        // Might not have read entire item last time
-        file.seek(SeekFrom::Start(state.pos))
-            .await
-            .map_err(|e| Error::Io(Box::new(e)))?;
-        let len = read_log_item_size(&mut file, state.pos).await?;
+        let first = state.segments.first();
+        if state.pos < first {
+            return Err(Error::PositionRemoved(first));
+        }
 
-        let next_pos = state.pos + 2 + 4 + (len as u64);
+        let base = state.segments.base_for(state.pos);
+        let file = state.segment_file(base).await?;
+        let offset = FILE_HEADER_SIZE + state.pos - base;
+        let (record_len, expected_checksum, record_start, checksum) =
+            read_item_start(&file, state.pos, offset).await?;
+        let len = record_len - record_start.len;
+
+        let next_pos = state.pos + item_size(record_len);
 
         Ok(LogItem {
             start_pos: state.pos,
-            file,
+            seq: state.seq,
             read: 0,
+            file,
+            contents_offset: offset + ITEM_HEADER_SIZE + record_start.len as u64,
+            fetched: 0,
+            buffered: Bytes::new(),
+            pending: None,
             len,
+            timestamp: record_start.timestamp,
+            metadata: record_start.metadata,
+            expected_checksum,
+            checksum,
             reader_state: ReaderState {
                 pos: next_pos,
-                tail_recv: state.tail_recv,
+                seq: state.seq + 1,
+                ..state
             },
         })
     }
 
-    pub async fn next<'a>(mut self, wait_for_more: bool) -> Result<NextItem, Error> {
+    pub async fn next(mut self, wait_for_more: bool) -> Result<NextItem, Error> {
         let mut log_tail: LogPosition = *self.state.tail_recv.borrow();
 
         while log_tail <= self.state.pos && wait_for_more {
@@ -197,4 +731,105 @@ impl Reader {
             Ok(NextItem::End(self))
         }
     }
+
+    /// Like `next`, but ends once one of the conditions in `options` is met, counting the item
+    /// returned against `max_items`. This lets callers stream each item's contents instead of
+    /// reading them into memory as `into_stream` does.
+    pub async fn next_with_options(self, options: &mut StreamOptions) -> Result<NextItem, Error> {
+        let end = options.max_items == Some(0)
+            || options.end_before == Some(self.position())
+            || options.end_before_seq.is_some_and(|seq| seq <= self.seq())
+            || options.end_after.is_some_and(|pos| pos < self.position());
+        if end {
+            return Ok(NextItem::End(self));
+        }
+
+        options.max_items = options.max_items.map(|max_items| max_items - 1);
+        self.next(options.wait_for_more).await
+    }
+
+    /// Reads the items as a stream, each read into memory in full, until one of the conditions
+    /// in `options` is met or the end of the log is reached.
+    pub fn into_stream(
+        self,
+        options: StreamOptions,
+    ) -> impl Stream<Item = Result<StreamItem, Error>> {
+        futures::stream::try_unfold((self, options), next_stream_item)
+    }
+
+    /// Like `next`, but if the item header or metadata at the reader's position is corrupt,
+    /// scans forward for the next item header which validates and from which the headers chain
+    /// through to the tail, and returns the range skipped with a reader at that item. If there is
+    /// no such item, everything up to the tail is skipped.
+    ///
+    /// A corrupt item's contents are only detected when they are read, an error is returned from
+    /// reading the `LogItem` but the reader from `LogItem::finish` can still be used.
+    pub async fn next_recovering(self, wait_for_more: bool) -> Result<Recovered, Error> {
+        let position = self.state.pos;
+        let path = self.state.path.clone();
+        let segments = self.state.segments.clone();
+        let files = self.state.files.clone();
+        let tail_recv = self.state.tail_recv.clone();
+
+        match self.next(wait_for_more).await {
+            Ok(NextItem::Item(item)) => Ok(Recovered::Item(item)),
+            Ok(NextItem::End(reader)) => Ok(Recovered::End(reader)),
+            Err(Error::InvalidItemChecksum) | Err(Error::InvalidMetadata) => {
+                let tail_pos = *tail_recv.borrow();
+                let resume_pos =
+                    find_next_item(&path, &segments.bases(), position + 1, tail_pos).await?;
+                let seq = files
+                    .offset_index()
+                    .await
+                    .map_err(|e| Error::Io(Box::new(e)))?
+                    .seq_for(resume_pos)
+                    .await
+                    .map_err(|e| Error::Io(Box::new(e)))?;
+
+                Ok(Recovered::Skipped(
+                    position..resume_pos,
+                    Reader {
+                        state: ReaderState {
+                            path,
+                            segments,
+                            files,
+                            tail_recv,
+                            pos: resume_pos,
+                            seq,
+                        },
+                    },
+                ))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+async fn next_stream_item(
+    (reader, mut options): (Reader, StreamOptions),
+) -> Result<Option<(StreamItem, (Reader, StreamOptions))>, Error> {
+    let mut log_item = match reader.next_with_options(&mut options).await? {
+        NextItem::Item(log_item) => log_item,
+        NextItem::End(_) => return Ok(None),
+    };
+
+    // The length is only checked once the contents have been read, so grow the buffer as they
+    // are rather than trusting it up front
+    let mut contents = Vec::with_capacity((log_item.len() as usize).min(READ_CHUNK_SIZE));
+    log_item.read_to_end(&mut contents).await.map_err(|e| {
+        // Reading the contents reports a bad checksum as an IO error
+        match e.get_ref().and_then(|inner| inner.downcast_ref::<Error>()) {
+            Some(Error::InvalidPayloadChecksum) => Error::InvalidPayloadChecksum,
+            _ => Error::Io(Box::new(e)),
+        }
+    })?;
+
+    let item = StreamItem {
+        position: log_item.position(),
+        seq: log_item.seq(),
+        timestamp: log_item.timestamp(),
+        metadata: std::mem::take(&mut log_item.metadata),
+        contents: Bytes::from(contents),
+    };
+    Ok(Some((item, (log_item.finish(), options))))
 }
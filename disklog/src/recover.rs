@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+use crate::checksum::calculate;
+use crate::format::FILE_HEADER_SIZE;
+use crate::reader::Error;
+use crate::segment::segment_path;
+use crate::{item_size, LogPosition, ITEM_HEADER_SIZE};
+
+/// How much of a segment is read at a time when scanning for an item header.
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The checksum and length at the start of an item header, which is all that can be checked
+/// without reading the item.
+const CHECKED_HEADER_SIZE: usize = 2 + 4;
+
+fn parse_header(bytes: &[u8]) -> (u16, u32) {
+    (
+        u16::from_be_bytes([bytes[0], bytes[1]]),
+        u32::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]),
+    )
+}
+
+/// The position a segment's items end at.
+fn segment_end(bases: &[LogPosition], base: LogPosition, tail_pos: LogPosition) -> LogPosition {
+    bases
+        .iter()
+        .find(|next_base| **next_base > base)
+        .copied()
+        .unwrap_or(tail_pos)
+        .min(tail_pos)
+}
+
+fn base_for(bases: &[LogPosition], position: LogPosition) -> LogPosition {
+    bases
+        .iter()
+        .rev()
+        .find(|base| **base <= position)
+        .copied()
+        .unwrap_or(bases[0])
+}
+
+/// Checks that following item headers from `position` lands exactly on the tail, with every
+/// item inside its segment. A header which only validates by chance will almost never chain.
+async fn chain_reaches_tail(
+    path: &Path,
+    bases: &[LogPosition],
+    mut position: LogPosition,
+    tail_pos: LogPosition,
+) -> Result<bool, Error> {
+    let mut segment: Option<(LogPosition, File)> = None;
+    while position < tail_pos {
+        let base = base_for(bases, position);
+        let file = match &mut segment {
+            Some((open_base, file)) if *open_base == base => file,
+            _ => {
+                let file = File::open(segment_path(path, base))
+                    .await
+                    .map_err(|e| Error::Io(Box::new(e)))?;
+                &mut segment.insert((base, file)).1
+            }
+        };
+        file.seek(SeekFrom::Start(FILE_HEADER_SIZE + position - base))
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        let mut header = [0u8; CHECKED_HEADER_SIZE];
+        match file.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(e) => return Err(Error::Io(Box::new(e))),
+        }
+
+        let (checksum, record_len) = parse_header(&header);
+        let next_pos = position + item_size(record_len);
+        if calculate(position, record_len) != checksum
+            || next_pos > segment_end(bases, base, tail_pos)
+        {
+            return Ok(false);
+        }
+        position = next_pos;
+    }
+    Ok(position == tail_pos)
+}
+
+/// Scans a segment from `position` for the first valid item header whose chain reaches the tail.
+async fn scan_segment(
+    path: &Path,
+    bases: &[LogPosition],
+    base: LogPosition,
+    position: LogPosition,
+    tail_pos: LogPosition,
+) -> Result<Option<LogPosition>, Error> {
+    let end = segment_end(bases, base, tail_pos);
+    let mut file = File::open(segment_path(path, base))
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+    file.seek(SeekFrom::Start(FILE_HEADER_SIZE + position - base))
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+
+    // The bytes of the segment from `buf_pos` onwards
+    let mut buf = Vec::new();
+    let mut buf_pos = position;
+    let mut candidate = position;
+    while candidate + ITEM_HEADER_SIZE <= end {
+        let mut offset = (candidate - buf_pos) as usize;
+        if offset + CHECKED_HEADER_SIZE > buf.len() {
+            buf.drain(..offset);
+            buf_pos = candidate;
+            offset = 0;
+
+            let unread = end - buf_pos - buf.len() as u64;
+            let mut chunk = vec![0u8; SCAN_CHUNK_SIZE.min(unread as usize)];
+            let read = file
+                .read(&mut chunk)
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+            if read == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..read]);
+            if buf.len() < CHECKED_HEADER_SIZE {
+                continue;
+            }
+        }
+
+        let (checksum, record_len) = parse_header(&buf[offset..offset + CHECKED_HEADER_SIZE]);
+        if calculate(candidate, record_len) == checksum
+            && candidate + item_size(record_len) <= end
+            && chain_reaches_tail(path, bases, candidate, tail_pos).await?
+        {
+            return Ok(Some(candidate));
+        }
+        candidate += 1;
+    }
+    Ok(None)
+}
+
+/// Finds the first item at or after `position` from which the log can be read through to the
+/// tail, or the tail if there is none.
+pub(crate) async fn find_next_item(
+    path: &Path,
+    bases: &[LogPosition],
+    mut position: LogPosition,
+    tail_pos: LogPosition,
+) -> Result<LogPosition, Error> {
+    while position < tail_pos {
+        let base = base_for(bases, position);
+        if let Some(found) = scan_segment(path, bases, base, position, tail_pos).await? {
+            return Ok(found);
+        }
+        position = segment_end(bases, base, tail_pos);
+    }
+    Ok(tail_pos)
+}
@@ -0,0 +1,58 @@
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::segment::segment_path;
+use crate::LogPosition;
+
+/// Limits on how much of a log is kept. Whole segments are removed, oldest first, and the
+/// segment currently being written to is always kept, so a log can exceed limits smaller
+/// than a segment.
+#[derive(Debug, Clone, Default)]
+pub struct RetentionPolicy {
+    /// Remove the oldest segments while the log is larger than this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Remove segments which have not been written to for this long.
+    pub max_age: Option<Duration>,
+    /// Remove the oldest segments while the log holds more than this many items.
+    pub max_items: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Returns how many of the oldest segments should be removed. `segment_items` holds the
+    /// number of items in each segment, and is only needed when the item count is limited.
+    pub(crate) async fn removable_segments(
+        &self,
+        path: &Path,
+        bases: &[LogPosition],
+        tail_pos: LogPosition,
+        segment_items: &[u64],
+    ) -> std::io::Result<usize> {
+        let now = SystemTime::now();
+        let mut bytes = tail_pos - bases[0];
+        let mut items: u64 = segment_items.iter().sum();
+
+        let mut remove = 0;
+        while remove + 1 < bases.len() {
+            let too_large = self.max_bytes.is_some_and(|max_bytes| bytes > max_bytes);
+            let too_many = self.max_items.is_some_and(|max_items| items > max_items);
+            let too_old = match self.max_age {
+                Some(max_age) => {
+                    let modified = tokio::fs::metadata(segment_path(path, bases[remove]))
+                        .await?
+                        .modified()?;
+                    now.duration_since(modified).is_ok_and(|age| age > max_age)
+                }
+                None => false,
+            };
+
+            if !(too_large || too_many || too_old) {
+                break;
+            }
+
+            bytes -= bases[remove + 1] - bases[remove];
+            items -= segment_items.get(remove).copied().unwrap_or(0);
+            remove += 1;
+        }
+        Ok(remove)
+    }
+}
@@ -0,0 +1,124 @@
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use futures::Stream;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncSeekExt, BufReader, SeekFrom};
+
+use crate::format::FILE_HEADER_SIZE;
+use crate::metadata::Metadata;
+use crate::offsetindex::SequenceNumber;
+use crate::reader::{read_log_item_header, read_record_start, Error, ReaderFactory};
+use crate::segment::{segment_path, Segments};
+use crate::timeindex::from_timestamp;
+use crate::{item_size, LogPosition, ITEM_TRAILER_SIZE};
+
+/// How much of a segment is read at a time while scanning.
+const SCAN_BUFFER_SIZE: usize = 64 * 1024;
+
+/// An item's header and metadata, read by `ReaderFactory::scan_headers` without its contents.
+#[derive(Debug, Clone)]
+pub struct ItemHeader {
+    pub position: LogPosition,
+    /// The number of items appended before this one.
+    pub seq: SequenceNumber,
+    /// The length of the contents in bytes.
+    pub len: u32,
+    /// When the item was appended.
+    pub timestamp: SystemTime,
+    pub metadata: Metadata,
+}
+
+struct Scan {
+    path: Box<Path>,
+    segments: Arc<Segments>,
+    /// The segment being read, with its base.
+    segment: Option<(LogPosition, BufReader<File>)>,
+    pos: LogPosition,
+    seq: SequenceNumber,
+    tail_pos: LogPosition,
+}
+
+impl ReaderFactory {
+    /// Reads the header and metadata of each item from `position` up to the tail, skipping the
+    /// contents. Each segment is read sequentially through a buffer, so this is much faster than
+    /// reading every item when building indexes or counting items.
+    ///
+    /// The header checksums are checked, but not the contents checksums as the contents are not
+    /// read.
+    pub async fn scan_headers(
+        &self,
+        position: LogPosition,
+    ) -> Result<impl Stream<Item = Result<ItemHeader, Error>>, Error> {
+        // Checks the position is the start of an item and finds its sequence number
+        let reader = self.read_from(position).await?;
+        let scan = Scan {
+            path: self.path.clone(),
+            segments: self.segments.clone(),
+            segment: None,
+            pos: reader.position(),
+            seq: reader.seq(),
+            tail_pos: self.tail_position(),
+        };
+        Ok(futures::stream::try_unfold(scan, next_header))
+    }
+}
+
+async fn next_header(mut scan: Scan) -> Result<Option<(ItemHeader, Scan)>, Error> {
+    if scan.pos >= scan.tail_pos {
+        return Ok(None);
+    }
+
+    let first = scan.segments.first();
+    if scan.pos < first {
+        return Err(Error::PositionRemoved(first));
+    }
+
+    let base = scan.segments.base_for(scan.pos);
+    let file = match &mut scan.segment {
+        Some((open_base, file)) if *open_base == base => file,
+        _ => {
+            let mut file = File::open(segment_path(&scan.path, base))
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+            file.seek(SeekFrom::Start(FILE_HEADER_SIZE + scan.pos - base))
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+            &mut scan
+                .segment
+                .insert((base, BufReader::with_capacity(SCAN_BUFFER_SIZE, file)))
+                .1
+        }
+    };
+
+    let (record_len, _) = read_log_item_header(file, scan.pos).await?;
+    let (record_start, _) = read_record_start(file, record_len).await?;
+    let len = record_len - record_start.len;
+    skip(file, len as u64 + ITEM_TRAILER_SIZE).await?;
+
+    let header = ItemHeader {
+        position: scan.pos,
+        seq: scan.seq,
+        len,
+        timestamp: from_timestamp(record_start.timestamp),
+        metadata: record_start.metadata,
+    };
+    scan.pos += item_size(record_len);
+    scan.seq += 1;
+    Ok(Some((header, scan)))
+}
+
+/// Skips over bytes, only seeking if they are not already buffered.
+async fn skip(file: &mut BufReader<File>, len: u64) -> Result<(), Error> {
+    if len <= file.buffer().len() as u64 {
+        Pin::new(file).consume(len as usize);
+    } else {
+        // Seeking from the current position accounts for what is left in the buffer
+        file.seek(SeekFrom::Current(len as i64))
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,236 @@
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use tokio::task::JoinHandle;
+
+use crate::format::{write_file_header, LOG_MAGIC};
+use crate::offsetindex::{offset_index_path, OffsetIndex};
+use crate::{LogPosition, OpenError};
+
+const SEGMENT_EXTENSION: &str = "log";
+
+/// Segment files are named after the position of their first item, padded so they sort.
+pub(crate) fn segment_path(path: &Path, base: LogPosition) -> PathBuf {
+    path.join(format!("{:020}.{}", base, SEGMENT_EXTENSION))
+}
+
+fn parse_segment_file_name(name: &str) -> Option<LogPosition> {
+    let base = name.strip_suffix(SEGMENT_EXTENSION)?.strip_suffix('.')?;
+    if base.len() == 20 && base.chars().all(|c| c.is_ascii_digit()) {
+        base.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// Returns the base positions of every segment in the log directory, in order.
+pub(crate) async fn list_segments(path: &Path) -> std::io::Result<Vec<LogPosition>> {
+    let mut bases = Vec::new();
+    let mut entries = tokio::fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(base) = entry.file_name().to_str().and_then(parse_segment_file_name) {
+            bases.push(base);
+        }
+    }
+    bases.sort_unstable();
+    Ok(bases)
+}
+
+/// Creates an empty segment, replacing any partially written segment left by a crash. The
+/// header is synced, but the directory entry is not.
+pub(crate) async fn create_segment_file(
+    path: &Path,
+    base: LogPosition,
+) -> Result<tokio::fs::File, OpenError> {
+    let mut segment_file = tokio::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(segment_path(path, base))
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    write_file_header(&mut segment_file, &LOG_MAGIC).await?;
+    segment_file
+        .sync_all()
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    Ok(segment_file)
+}
+
+/// The base positions of the segments of a log, shared between the writer and readers.
+pub(crate) struct Segments {
+    bases: RwLock<Vec<LogPosition>>,
+}
+
+impl Segments {
+    pub fn new(bases: Vec<LogPosition>) -> Segments {
+        Segments {
+            bases: RwLock::new(bases),
+        }
+    }
+
+    /// Finds the base position of the segment an item at `position` would be stored in.
+    pub fn base_for(&self, position: LogPosition) -> LogPosition {
+        let bases = self.bases.read().expect("Segments lock poisoned");
+        match bases.binary_search(&position) {
+            Ok(index) => bases[index],
+            Err(0) => bases[0],
+            Err(index) => bases[index - 1],
+        }
+    }
+
+    /// The position of the first item which has not been removed.
+    pub fn first(&self) -> LogPosition {
+        self.bases.read().expect("Segments lock poisoned")[0]
+    }
+
+    pub fn bases(&self) -> Vec<LogPosition> {
+        self.bases.read().expect("Segments lock poisoned").clone()
+    }
+
+    pub fn remove_first(&self) {
+        self.bases
+            .write()
+            .expect("Segments lock poisoned")
+            .remove(0);
+    }
+
+    pub fn push(&self, base: LogPosition) {
+        self.bases
+            .write()
+            .expect("Segments lock poisoned")
+            .push(base);
+    }
+}
+
+/// Read handles for the segments and offset index of a log, opened once and shared by every
+/// reader so the number of open files does not grow with the number of readers. Reads use
+/// positional reads, so readers never move a shared file offset.
+pub(crate) struct SegmentFiles {
+    path: Box<Path>,
+    segments: Arc<Segments>,
+    files: Mutex<Vec<(LogPosition, Arc<std::fs::File>)>>,
+    /// The offset index with the first segment when it was opened, as the index is replaced
+    /// before segments are removed.
+    offset_index: Mutex<Option<(LogPosition, Arc<std::fs::File>)>>,
+}
+
+impl SegmentFiles {
+    pub fn new(path: Box<Path>, segments: Arc<Segments>) -> SegmentFiles {
+        SegmentFiles {
+            path,
+            segments,
+            files: Mutex::new(Vec::new()),
+            offset_index: Mutex::new(None),
+        }
+    }
+
+    fn find(&self, base: LogPosition) -> Option<Arc<std::fs::File>> {
+        let mut files = self.files.lock().expect("Segment files lock poisoned");
+        // Close the segments removed by the retention policy
+        let first = self.segments.first();
+        files.retain(|(file_base, _)| *file_base >= first);
+        files
+            .iter()
+            .find(|(file_base, _)| *file_base == base)
+            .map(|(_, file)| file.clone())
+    }
+
+    /// The read handle for the segment starting at `base`, opening it if needed.
+    pub async fn get(&self, base: LogPosition) -> std::io::Result<Arc<std::fs::File>> {
+        if let Some(file) = self.find(base) {
+            return Ok(file);
+        }
+
+        let file = tokio::fs::File::open(segment_path(&self.path, base))
+            .await?
+            .into_std()
+            .await;
+
+        // Another reader might have opened it in the meantime
+        let mut files = self.files.lock().expect("Segment files lock poisoned");
+        match files.iter().find(|(file_base, _)| *file_base == base) {
+            Some((_, file)) => Ok(file.clone()),
+            None => {
+                let file = Arc::new(file);
+                files.push((base, file.clone()));
+                Ok(file)
+            }
+        }
+    }
+
+    /// The offset index, opening its read handle if needed or if segments have been removed
+    /// since it was opened.
+    pub async fn offset_index(&self) -> std::io::Result<OffsetIndex> {
+        let first = self.segments.first();
+        let opened = self
+            .offset_index
+            .lock()
+            .expect("Offset index lock poisoned")
+            .clone()
+            .filter(|(opened_first, _)| *opened_first == first);
+        let file = match opened {
+            Some((_, file)) => file,
+            None => {
+                let file = tokio::fs::File::open(offset_index_path(&self.path))
+                    .await?
+                    .into_std()
+                    .await;
+
+                // Another reader might have opened it in the meantime
+                let mut offset_index = self
+                    .offset_index
+                    .lock()
+                    .expect("Offset index lock poisoned");
+                match &*offset_index {
+                    Some((opened_first, file)) if *opened_first == first => file.clone(),
+                    _ => {
+                        let file = Arc::new(file);
+                        *offset_index = Some((first, file.clone()));
+                        file
+                    }
+                }
+            }
+        };
+        Ok(OffsetIndex::new(file))
+    }
+}
+
+/// Reads up to `len` bytes at `offset` on the blocking pool, fewer if the file ends first.
+pub(crate) fn spawn_read_at(
+    file: Arc<std::fs::File>,
+    offset: u64,
+    len: usize,
+) -> JoinHandle<std::io::Result<Vec<u8>>> {
+    tokio::task::spawn_blocking(move || {
+        let mut bytes = vec![0u8; len];
+        let mut filled = 0;
+        while filled < len {
+            match file.read_at(&mut bytes[filled..], offset + filled as u64) {
+                Ok(0) => break,
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        bytes.truncate(filled);
+        Ok(bytes)
+    })
+}
+
+/// Reads exactly `len` bytes at `offset` on the blocking pool.
+pub(crate) async fn read_exact_at(
+    file: Arc<std::fs::File>,
+    offset: u64,
+    len: usize,
+) -> std::io::Result<Vec<u8>> {
+    let bytes = spawn_read_at(file, offset, len)
+        .await
+        .map_err(std::io::Error::other)??;
+    if bytes.len() < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
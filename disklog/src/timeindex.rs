@@ -0,0 +1,219 @@
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::format::{
+    check_file_version, parse_file_version, replace_file, write_file_header, FILE_HEADER_SIZE,
+};
+use crate::{LogPosition, OpenError, U64SIZE};
+
+const TIME_INDEX_MAGIC: [u8; 4] = *b"DTIM";
+
+/// An entry is added once this many bytes have been appended since the last one.
+const TIME_INDEX_INTERVAL: u64 = 64 * 1024;
+
+/// Timestamp and position.
+const ENTRY_SIZE: usize = 2 * U64SIZE;
+
+/// Item timestamps are stored as milliseconds since the Unix epoch.
+pub(crate) type Timestamp = u64;
+
+pub(crate) fn to_timestamp(time: SystemTime) -> Timestamp {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis().try_into().unwrap_or(Timestamp::MAX))
+        .unwrap_or(0)
+}
+
+pub(crate) fn from_timestamp(timestamp: Timestamp) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(timestamp)
+}
+
+pub(crate) fn time_index_path(path: &Path) -> PathBuf {
+    path.join("timeindex")
+}
+
+/// Parses the entries after the header, ignoring a partially written last entry.
+fn parse_entries(contents: &[u8]) -> Vec<(Timestamp, LogPosition)> {
+    contents
+        .get(FILE_HEADER_SIZE as usize..)
+        .unwrap_or(&[])
+        .chunks_exact(ENTRY_SIZE)
+        .map(|entry| {
+            let (timestamp, position) = entry.split_at(U64SIZE);
+            (
+                u64::from_be_bytes(timestamp.try_into().expect("Entry is two u64s")),
+                u64::from_be_bytes(position.try_into().expect("Entry is two u64s")),
+            )
+        })
+        .collect()
+}
+
+/// A sparse index of item timestamps, so reading from a time does not need to scan the log.
+///
+/// The index is only a hint and is not synced, entries at or after the tail are dropped when
+/// the log is opened. Timestamps never go backwards, so the entries are sorted.
+pub(crate) struct TimeIndexWriter {
+    file: File,
+    last_entry: Option<LogPosition>,
+    /// The timestamp of the last item appended.
+    last_timestamp: Timestamp,
+}
+
+impl TimeIndexWriter {
+    pub(crate) async fn open(
+        path: &Path,
+        tail_pos: LogPosition,
+    ) -> Result<TimeIndexWriter, OpenError> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(time_index_path(path))
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        if contents.is_empty() {
+            write_file_header(&mut file, &TIME_INDEX_MAGIC).await?;
+            return Ok(TimeIndexWriter {
+                file,
+                last_entry: None,
+                last_timestamp: 0,
+            });
+        }
+        check_file_version(parse_file_version(&contents, &TIME_INDEX_MAGIC))?;
+
+        // Drop entries for items which were never committed, they might be overwritten
+        let mut writer = TimeIndexWriter {
+            file,
+            last_entry: None,
+            last_timestamp: 0,
+        };
+        writer
+            .truncate_entries(&contents, tail_pos)
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        Ok(writer)
+    }
+
+    /// Keeps only the entries in `contents` for items before `position`.
+    async fn truncate_entries(
+        &mut self,
+        contents: &[u8],
+        position: LogPosition,
+    ) -> std::io::Result<()> {
+        let mut entries = parse_entries(contents);
+        entries.retain(|(_, entry_position)| *entry_position < position);
+        let len = FILE_HEADER_SIZE + (entries.len() * ENTRY_SIZE) as u64;
+        self.file.set_len(len).await?;
+        self.file.seek(std::io::SeekFrom::Start(len)).await?;
+
+        let last = entries.last();
+        self.last_entry = last.map(|(_, position)| *position);
+        self.last_timestamp = last.map(|(timestamp, _)| *timestamp).unwrap_or(0);
+        Ok(())
+    }
+
+    async fn read_contents(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        self.file.seek(std::io::SeekFrom::Start(0)).await?;
+        self.file.read_to_end(&mut contents).await?;
+        Ok(contents)
+    }
+
+    /// Removes the entries for items at or after `position`, see `SegmentWriter::discard_from`.
+    pub(crate) async fn discard_from(&mut self, position: LogPosition) -> std::io::Result<()> {
+        let contents = self.read_contents().await?;
+        self.truncate_entries(&contents, position).await
+    }
+
+    /// Removes the entries for items before `position`, once the retention policy removes the
+    /// segments holding them.
+    pub(crate) async fn remove_before(
+        &mut self,
+        path: &Path,
+        position: LogPosition,
+    ) -> Result<(), OpenError> {
+        let contents = self
+            .read_contents()
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        let entries = parse_entries(&contents);
+        let removed = entries.partition_point(|(_, entry_position)| *entry_position < position);
+        if removed == 0 {
+            return Ok(());
+        }
+
+        let start = FILE_HEADER_SIZE as usize + removed * ENTRY_SIZE;
+        let end = FILE_HEADER_SIZE as usize + entries.len() * ENTRY_SIZE;
+        self.file = replace_file(
+            &time_index_path(path),
+            &TIME_INDEX_MAGIC,
+            &contents[start..end],
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// The timestamp for a new item, which is never before the previous one.
+    pub(crate) fn next_timestamp(&self) -> Timestamp {
+        to_timestamp(SystemTime::now()).max(self.last_timestamp)
+    }
+
+    /// Records the timestamp of an item, adding an index entry if it is due. Every segment
+    /// starts with an entry.
+    pub(crate) async fn add(
+        &mut self,
+        position: LogPosition,
+        timestamp: Timestamp,
+        segment_start: bool,
+    ) -> std::io::Result<()> {
+        self.last_timestamp = self.last_timestamp.max(timestamp);
+
+        let due = match self.last_entry {
+            None => true,
+            Some(last_entry) => segment_start || position >= last_entry + TIME_INDEX_INTERVAL,
+        };
+        if due {
+            let mut entry = [0u8; ENTRY_SIZE];
+            entry[..U64SIZE].copy_from_slice(&timestamp.to_be_bytes());
+            entry[U64SIZE..].copy_from_slice(&position.to_be_bytes());
+            self.file.write_all(&entry).await?;
+            self.file.flush().await?;
+            self.last_entry = Some(position);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the position of the last indexed item written before `timestamp`, the first item
+/// written at or after it can be found by reading forward from there.
+pub(crate) async fn find_position_before(
+    path: &Path,
+    timestamp: Timestamp,
+    tail_pos: LogPosition,
+) -> std::io::Result<Option<LogPosition>> {
+    let contents = match tokio::fs::read(time_index_path(path)).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if parse_file_version(&contents, &TIME_INDEX_MAGIC).is_none() {
+        return Ok(None);
+    }
+
+    let entries = parse_entries(&contents);
+    let before = entries.partition_point(|(entry_timestamp, _)| *entry_timestamp < timestamp);
+    Ok(entries[..before]
+        .iter()
+        .rev()
+        .map(|(_, position)| *position)
+        .find(|position| *position < tail_pos))
+}
@@ -0,0 +1,204 @@
+use std::path::Path;
+
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+
+use crate::checksum::{calculate, update_payload, PayloadChecksum};
+use crate::format::{
+    check_file_version, parse_file_version, FILE_HEADER_SIZE, LOG_MAGIC, TAIL_MAGIC,
+};
+use crate::metadata::Metadata;
+use crate::open::{parse_tail_records, read_log_position};
+use crate::segment::{list_segments, segment_path};
+use crate::{
+    item_size, LogPosition, OpenError, ITEM_HEADER_SIZE, ITEM_TRAILER_SIZE, METADATA_LEN_SIZE,
+    TIMESTAMP_SIZE,
+};
+
+/// What was wrong with the first bad item found by `verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    /// The item header checksum did not match its position and length.
+    ItemHeader,
+    /// The item metadata could not be read.
+    Metadata,
+    /// The item contents did not match the checksum written with them.
+    Payload,
+    /// The item runs past the end of its segment or the tail.
+    Overrun,
+    /// The segment file ends before the tail.
+    Truncated,
+    /// The item trailer did not match its header.
+    Trailer,
+}
+
+impl std::fmt::Display for Corruption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Corruption::ItemHeader => write!(f, "Item checksum failed"),
+            Corruption::Metadata => write!(f, "Invalid item metadata"),
+            Corruption::Payload => write!(f, "Item contents checksum failed"),
+            Corruption::Overrun => write!(f, "Item runs past the end of its segment"),
+            Corruption::Truncated => write!(f, "Segment ends before the tail"),
+            Corruption::Trailer => write!(f, "Item trailer does not match its header"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    pub tail_pos: LogPosition,
+    /// False if one of the two tail records failed its checksum, the newest valid one is used.
+    pub tail_records_valid: bool,
+    /// The position verifying started from, items before it were removed by retention.
+    pub first_position: LogPosition,
+    pub segments: u64,
+    /// The number of items which passed verification.
+    pub items: u64,
+    /// The first bad item, verifying stops there.
+    pub first_bad: Option<(LogPosition, Corruption)>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.tail_records_valid && self.first_bad.is_none()
+    }
+}
+
+/// Reads both tail records, returning the newest valid position and whether both are valid.
+async fn read_tail(path: &Path) -> Result<(LogPosition, bool), OpenError> {
+    let contents = tokio::fs::read(path.join("tail"))
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    check_file_version(parse_file_version(&contents, &TAIL_MAGIC))?;
+    let records = parse_tail_records(&contents)?;
+    let record = read_log_position(&contents)?;
+    Ok((record.position, records.iter().all(Option::is_some)))
+}
+
+/// Reads the remaining bytes of a record, returning their checksum.
+async fn checksum_rest<R: AsyncRead + Unpin>(
+    file: &mut R,
+    mut checksum: PayloadChecksum,
+    mut len: u64,
+) -> std::io::Result<PayloadChecksum> {
+    let mut buf = vec![0u8; 64 * 1024];
+    while len > 0 {
+        let chunk = len.min(buf.len() as u64) as usize;
+        file.read_exact(&mut buf[..chunk]).await?;
+        checksum = update_payload(checksum, &buf[..chunk]);
+        len -= chunk as u64;
+    }
+    Ok(checksum)
+}
+
+/// Checks the items in one segment, returning how many were good and the first bad one.
+pub(crate) async fn verify_segment(
+    file: File,
+    base: LogPosition,
+    end: LogPosition,
+) -> (u64, Option<(LogPosition, Corruption)>) {
+    let mut file = BufReader::new(file);
+    let mut items = 0;
+    let mut pos = base;
+    while pos < end {
+        let mut header = [0u8; ITEM_HEADER_SIZE as usize];
+        if file.read_exact(&mut header).await.is_err() {
+            return (items, Some((pos, Corruption::Truncated)));
+        }
+        let checksum = u16::from_be_bytes([header[0], header[1]]);
+        let record_len = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+        let expected = u32::from_be_bytes([header[6], header[7], header[8], header[9]]);
+        if calculate(pos, record_len) != checksum {
+            return (items, Some((pos, Corruption::ItemHeader)));
+        }
+        let next_pos = pos + item_size(record_len);
+        if next_pos > end {
+            return (items, Some((pos, Corruption::Overrun)));
+        }
+
+        let mut record_start = [0u8; (TIMESTAMP_SIZE + METADATA_LEN_SIZE) as usize];
+        if file.read_exact(&mut record_start).await.is_err() {
+            return (items, Some((pos, Corruption::Truncated)));
+        }
+        let metadata_len = u16::from_be_bytes([record_start[8], record_start[9]]) as u64;
+        let rest = match (record_len as u64).checked_sub(record_start.len() as u64) {
+            Some(rest) if rest >= metadata_len => rest,
+            _ => return (items, Some((pos, Corruption::Metadata))),
+        };
+        let mut metadata = vec![0u8; metadata_len as usize];
+        if file.read_exact(&mut metadata).await.is_err() {
+            return (items, Some((pos, Corruption::Truncated)));
+        }
+        if Metadata::decode(&metadata).is_none() {
+            return (items, Some((pos, Corruption::Metadata)));
+        }
+
+        let checksum = update_payload(update_payload(0, &record_start), &metadata);
+        match checksum_rest(&mut file, checksum, rest - metadata_len).await {
+            Ok(checksum) if checksum == expected => {}
+            Ok(_) => return (items, Some((pos, Corruption::Payload))),
+            Err(_) => return (items, Some((pos, Corruption::Truncated))),
+        }
+
+        let mut trailer = [0u8; ITEM_TRAILER_SIZE as usize];
+        if file.read_exact(&mut trailer).await.is_err() {
+            return (items, Some((pos, Corruption::Truncated)));
+        }
+        if trailer[..] != header[..ITEM_TRAILER_SIZE as usize] {
+            return (items, Some((pos, Corruption::Trailer)));
+        }
+
+        items += 1;
+        pos = next_pos;
+    }
+    (items, None)
+}
+
+/// Reads every item still in the log, checking the header, trailer and contents checksums, and
+/// checks both tail records are valid.
+///
+/// The log is not locked, verifying a log while it is being written to can report a tail record
+/// as damaged.
+pub async fn verify(path: impl AsRef<Path>) -> Result<VerifyReport, OpenError> {
+    let path = path.as_ref();
+    let (tail_pos, tail_records_valid) = read_tail(path).await?;
+
+    let mut bases = list_segments(path)
+        .await
+        .map_err(|e| OpenError::Io(Box::new(e)))?;
+    bases.retain(|base| *base <= tail_pos);
+    if bases.is_empty() {
+        return Err(OpenError::LogTooSmall);
+    }
+
+    let mut report = VerifyReport {
+        tail_pos,
+        tail_records_valid,
+        first_position: bases[0],
+        segments: 0,
+        items: 0,
+        first_bad: None,
+    };
+    for (index, base) in bases.iter().enumerate() {
+        let end = bases.get(index + 1).copied().unwrap_or(tail_pos);
+
+        let mut file = File::open(segment_path(path, *base))
+            .await
+            .map_err(|e| OpenError::Io(Box::new(e)))?;
+        let mut header = [0u8; FILE_HEADER_SIZE as usize];
+        file.read_exact(&mut header)
+            .await
+            .map_err(|_| OpenError::InvalidFileHeader)?;
+        check_file_version(parse_file_version(&header, &LOG_MAGIC))?;
+
+        let (items, first_bad) = verify_segment(file, *base, end).await;
+        report.segments += 1;
+        report.items += items;
+        if first_bad.is_some() {
+            report.first_bad = first_bad;
+            break;
+        }
+    }
+    Ok(report)
+}
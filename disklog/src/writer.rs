@@ -1,15 +1,70 @@
 use std::convert::TryInto;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWriteExt, ReadBuf};
 
 use crate::checksum;
-use crate::LogPosition;
+use crate::format::FILE_HEADER_SIZE;
+use crate::metadata::Metadata;
+use crate::offsetindex::OffsetIndexWriter;
+use crate::open::TailRecord;
+use crate::retention::RetentionPolicy;
+use crate::segment::{create_segment_file, segment_path, Segments};
+use crate::timeindex::{TimeIndexWriter, Timestamp};
+use crate::{item_size, LogPosition};
 
-pub struct Writer {
+/// When appended items are synced to disk before `Writer::append` returns.
+///
+/// Whenever a sync happens the log is synced before the tail file, so the tail never points
+/// past data which is on disk. Between syncs a power failure can lose recent appends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Every append is on disk before it returns.
+    Always,
+    /// Sync during an append if this long has passed since the last sync. The last appends
+    /// before the log goes idle are only synced by `Writer::sync_if_due`, which should be called
+    /// about this often.
+    Interval(Duration),
+    /// Sync during an append once this many bytes have been appended since the last sync.
+    Bytes(u64),
+    /// Leave writing to disk to the operating system.
+    Never,
+}
+
+/// Writes items into the segment files, ahead of the tail other readers can see.
+pub(crate) struct SegmentWriter {
+    pub(crate) path: Box<Path>,
+    pub(crate) segment_size: u64,
+    pub(crate) segments: Arc<Segments>,
+    /// The position of the first item in the segment `log_file` refers to.
+    pub(crate) segment_base: LogPosition,
+    pub(crate) retention: RetentionPolicy,
+    pub(crate) time_index: TimeIndexWriter,
+    pub(crate) offset_index: OffsetIndexWriter,
     pub(crate) log_file: tokio::fs::File,
+    /// Where the next item will be written.
+    pub(crate) write_pos: LogPosition,
+}
+
+/// Records and publishes the tail once the items before it are written.
+pub(crate) struct TailWriter {
     pub(crate) tail_file: tokio::fs::File,
     pub(crate) tail_sender: tokio::sync::watch::Sender<LogPosition>,
-    pub(crate) tail_pos: u64,
+    pub(crate) tail_pos: LogPosition,
+    /// The generation of the last tail record written.
+    pub(crate) generation: u64,
+    pub(crate) sync_policy: SyncPolicy,
+    pub(crate) unsynced_bytes: u64,
+    pub(crate) last_sync: Instant,
+}
+
+pub struct Writer {
+    pub(crate) segment_writer: SegmentWriter,
+    pub(crate) tail_writer: TailWriter,
 }
 
 #[derive(Debug)]
@@ -17,6 +72,10 @@ pub enum Error {
     Io(Box<dyn std::error::Error + Send + Sync>),
     /// Log items are limited to 2^32 bytes.
     ItemTooLarge,
+    /// The log did not end at the expected position, contains the position it does end at.
+    TailMoved(LogPosition),
+    /// Encoded metadata is limited to `MAX_METADATA_SIZE` bytes.
+    MetadataTooLarge,
 }
 
 impl std::fmt::Display for Error {
@@ -24,19 +83,184 @@ impl std::fmt::Display for Error {
         match self {
             Error::Io(e) => e.fmt(f),
             Error::ItemTooLarge => write!(f, "Item too large"),
+            Error::TailMoved(tail_pos) => write!(f, "Log tail has moved to {}", tail_pos),
+            Error::MetadataTooLarge => write!(f, "Item metadata too large"),
         }
     }
 }
 
 impl std::error::Error for Error {}
 
-impl Writer {
-    async fn append_item<Contents: AsyncRead + Unpin>(
+/// Calculates the contents checksum while the contents are streamed into the log.
+pub(crate) struct ChecksumReader<'a, Contents> {
+    pub(crate) contents: &'a mut Contents,
+    pub(crate) checksum: checksum::PayloadChecksum,
+}
+
+impl<'a, Contents: AsyncRead + Unpin> AsyncRead for ChecksumReader<'a, Contents> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let length_before = buf.filled().len();
+        let res = Pin::new(&mut *self.contents).poll_read(cx, buf);
+        self.checksum = checksum::update_payload(self.checksum, &buf.filled()[length_before..]);
+        res
+    }
+}
+
+async fn sync_dir(path: &Path) -> Result<(), Error> {
+    let dir = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| Error::Io(Box::new(e)))?;
+    dir.sync_all().await.map_err(|e| Error::Io(Box::new(e)))
+}
+
+impl SegmentWriter {
+    fn segment_offset(&self, position: LogPosition) -> u64 {
+        FILE_HEADER_SIZE + position - self.segment_base
+    }
+
+    async fn start_segment(&mut self) -> Result<(), Error> {
+        // Only the current segment is synced when committing, so finish with this one now
+        self.log_file
+            .sync_data()
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+
+        // The new segment must survive a crash once the tail moves into it
+        self.log_file = create_segment_file(&self.path, self.write_pos)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        sync_dir(&self.path).await?;
+        self.segment_base = self.write_pos;
+        self.segments.push(self.write_pos);
+        Ok(())
+    }
+
+    pub(crate) async fn enforce_retention(&mut self) -> Result<(), Error> {
+        let bases = self.segments.bases();
+        let segment_items = if self.retention.max_items.is_some() {
+            self.offset_index
+                .segment_items(&bases)
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?
+        } else {
+            Vec::new()
+        };
+        let remove = self
+            .retention
+            .removable_segments(&self.path, &bases, self.write_pos, &segment_items)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+
+        // Before the segments are removed, so readers which see them removed find the new index
+        let first = bases[remove];
+        self.offset_index
+            .remove_before(&self.path, first)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        self.time_index
+            .remove_before(&self.path, first)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+
+        for base in &bases[..remove] {
+            // Stop new readers from finding the segment before it is deleted
+            self.segments.remove_first();
+            tokio::fs::remove_file(segment_path(&self.path, *base))
+                .await
+                .map_err(|e| Error::Io(Box::new(e)))?;
+        }
+        Ok(())
+    }
+
+    /// Starts a new segment if the current one is full.
+    pub(crate) async fn roll_segment(&mut self) -> Result<(), Error> {
+        let segment_len = self.write_pos - self.segment_base;
+        if segment_len > 0 && segment_len >= self.segment_size {
+            self.start_segment().await?;
+            self.enforce_retention().await?;
+        }
+        Ok(())
+    }
+
+    /// Forgets items written since `position`, which will be overwritten by the next append.
+    /// Must not be used across a segment boundary.
+    pub(crate) async fn discard_from(&mut self, position: LogPosition) -> Result<(), Error> {
+        self.write_pos = position;
+        self.offset_index
+            .discard_from(position)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        self.time_index
+            .discard_from(position)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))
+    }
+
+    /// Writes an item after the previous one, returning its position.
+    pub(crate) async fn append_item<Contents: AsyncRead + Unpin>(
         &mut self,
+        metadata: &Metadata,
         contents: &mut Contents,
     ) -> Result<LogPosition, Error> {
+        let timestamp = self.time_index.next_timestamp();
+        self.append_item_at(timestamp, metadata, contents).await
+    }
+
+    /// Writes an item with a given timestamp, which should not be before the previous item's.
+    pub(crate) async fn append_item_at<Contents: AsyncRead + Unpin>(
+        &mut self,
+        timestamp: Timestamp,
+        metadata: &Metadata,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        self.roll_segment().await?;
+        self.write_item(timestamp, metadata, contents).await
+    }
+
+    /// Writes a batch of items into the same segment, returning their positions. If any item
+    /// fails none of the batch is kept.
+    pub(crate) async fn append_items<Contents: AsyncRead + Unpin>(
+        &mut self,
+        items: &mut [Contents],
+    ) -> Result<Vec<LogPosition>, Error> {
+        self.roll_segment().await?;
+
+        let batch_pos = self.write_pos;
+        let mut positions = Vec::with_capacity(items.len());
+        for contents in items.iter_mut() {
+            let timestamp = self.time_index.next_timestamp();
+            match self.write_item(timestamp, &Metadata::new(), contents).await {
+                Ok(position) => positions.push(position),
+                Err(e) => {
+                    // A failed item may already have an index entry, so the index decides how
+                    // many items to forget
+                    self.discard_from(batch_pos).await?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(positions)
+    }
+
+    async fn write_item<Contents: AsyncRead + Unpin>(
+        &mut self,
+        timestamp: Timestamp,
+        metadata: &Metadata,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        let metadata = metadata.encode().ok_or(Error::MetadataTooLarge)?;
+        let mut record_start = timestamp.to_be_bytes().to_vec();
+        record_start.extend_from_slice(&(metadata.len() as u16).to_be_bytes());
+        record_start.extend_from_slice(&metadata);
+
         self.log_file
-            .seek(tokio::io::SeekFrom::Start(self.tail_pos))
+            .seek(tokio::io::SeekFrom::Start(
+                self.segment_offset(self.write_pos),
+            ))
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
 
@@ -49,26 +273,54 @@ impl Writer {
             .write_u32(0u32)
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
+        self.log_file
+            .write_u32(0u32)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+
+        self.log_file
+            .write_all(&record_start)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
 
-        let bytes_written: u32 = tokio::io::copy(contents, &mut self.log_file)
+        let mut contents = ChecksumReader {
+            contents,
+            checksum: checksum::update_payload(0, &record_start),
+        };
+        let bytes_written = tokio::io::copy(&mut contents, &mut self.log_file)
             .await
-            .map_err(|e| Error::Io(Box::new(e)))?
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        let record_len: u32 = (record_start.len() as u64 + bytes_written)
             .try_into()
             .map_err(|_| Error::ItemTooLarge)?;
+        let checksum = checksum::calculate(self.write_pos, record_len);
 
+        // The trailer repeats the start of the header, so the item can be found from its end
+        self.log_file
+            .write_u16(checksum)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
         self.log_file
-            .seek(tokio::io::SeekFrom::Start(self.tail_pos))
+            .write_u32(record_len)
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
 
-        let checksum = checksum::calculate(self.tail_pos, bytes_written);
-
+        self.log_file
+            .seek(tokio::io::SeekFrom::Start(
+                self.segment_offset(self.write_pos),
+            ))
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
         self.log_file
             .write_u16(checksum)
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
         self.log_file
-            .write_u32(bytes_written)
+            .write_u32(record_len)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        self.log_file
+            .write_u32(contents.checksum)
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
 
@@ -77,42 +329,201 @@ impl Writer {
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
 
-        Ok(self.tail_pos + 2 + 4 + (bytes_written as u64))
+        let position = self.write_pos;
+        self.write_pos += item_size(record_len);
+        self.offset_index
+            .add(position)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        self.time_index
+            .add(position, timestamp, position == self.segment_base)
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        Ok(position)
+    }
+
+    pub(crate) async fn sync_log_file(&mut self) -> Result<(), Error> {
+        self.log_file
+            .sync_data()
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))
+    }
+}
+
+impl TailWriter {
+    /// Whether the log must be synced before the tail is moved to `new_tail_pos`.
+    pub(crate) fn sync_due(&self, new_tail_pos: LogPosition) -> bool {
+        match self.sync_policy {
+            SyncPolicy::Always => true,
+            SyncPolicy::Interval(interval) => self.last_sync.elapsed() >= interval,
+            SyncPolicy::Bytes(bytes) => self.unsynced_bytes + new_tail_pos - self.tail_pos >= bytes,
+            SyncPolicy::Never => false,
+        }
+    }
+
+    /// Whether committed appends have been left unsynced for longer than the policy allows.
+    pub(crate) fn idle_sync_due(&self) -> bool {
+        self.unsynced_bytes > 0 && self.sync_due(self.tail_pos)
+    }
+
+    pub(crate) async fn sync_tail_file(&mut self) -> Result<(), Error> {
+        self.tail_file
+            .sync_data()
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        self.unsynced_bytes = 0;
+        self.last_sync = Instant::now();
+        Ok(())
     }
 
     async fn write_tail_file(&mut self, new_tail_pos: LogPosition) -> Result<(), Error> {
+        // Only move on to the next slot once the record is written, so a failed write can
+        // never leave both slots damaged
+        let record = TailRecord {
+            generation: self.generation + 1,
+            position: new_tail_pos,
+        };
         self.tail_file
-            .seek(tokio::io::SeekFrom::Start(0))
+            .seek(tokio::io::SeekFrom::Start(record.slot_offset()))
+            .await
+            .map_err(|e| Error::Io(Box::new(e)))?;
+        self.tail_file
+            .write_all(&record.encode())
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
-        for _ in 0..3 {
-            self.tail_file
-                .write_u64(new_tail_pos)
-                .await
-                .map_err(|e| Error::Io(Box::new(e)))?;
-        }
         self.tail_file
             .flush()
             .await
             .map_err(|e| Error::Io(Box::new(e)))?;
+        self.generation = record.generation;
         Ok(())
     }
 
-    pub async fn append<Contents: AsyncRead + Unpin>(
+    /// Moves the tail, the log must already be synced if `synced_log` is set.
+    pub(crate) async fn commit(
         &mut self,
-        contents: &mut Contents,
-    ) -> Result<LogPosition, Error> {
-        let old_tail_pos = self.tail_pos;
-
-        let new_tail_pos = self.append_item(contents).await?;
+        new_tail_pos: LogPosition,
+        synced_log: bool,
+    ) -> Result<(), Error> {
         self.write_tail_file(new_tail_pos).await?;
+        if synced_log {
+            self.sync_tail_file().await?;
+        } else {
+            self.unsynced_bytes += new_tail_pos - self.tail_pos;
+        }
 
         self.tail_pos = new_tail_pos;
 
         self.tail_sender
             .send(new_tail_pos)
-            .map_err(|e| Error::Io(Box::new(e)))?;
+            .map_err(|e| Error::Io(Box::new(e)))
+    }
+}
+
+impl Writer {
+    /// Removes the oldest segments not allowed by the retention policy.
+    ///
+    /// This happens whenever a new segment is started, but an idle log can be aged out by
+    /// calling it periodically.
+    pub async fn enforce_retention(&mut self) -> Result<(), Error> {
+        self.segment_writer.enforce_retention().await
+    }
+
+    /// Makes every append so far durable, regardless of the sync policy.
+    pub async fn sync(&mut self) -> Result<(), Error> {
+        self.segment_writer.sync_log_file().await?;
+        self.tail_writer.sync_tail_file().await
+    }
+
+    /// Syncs appends which have been left unsynced for longer than `SyncPolicy::Interval`
+    /// allows. Appends only sync earlier appends, so an idle log needs this called periodically.
+    pub async fn sync_if_due(&mut self) -> Result<(), Error> {
+        if self.tail_writer.idle_sync_due() {
+            self.sync().await
+        } else {
+            Ok(())
+        }
+    }
+
+    pub async fn append<Contents: AsyncRead + Unpin>(
+        &mut self,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        self.append_with_metadata(&Metadata::new(), contents).await
+    }
+
+    /// Appends an item with metadata, which readers get from `LogItem::metadata`.
+    pub async fn append_with_metadata<Contents: AsyncRead + Unpin>(
+        &mut self,
+        metadata: &Metadata,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        let timestamp = self.segment_writer.time_index.next_timestamp();
+        self.append_at(timestamp, metadata, contents).await
+    }
+
+    /// Appends an item with a given timestamp, used to copy items from another log.
+    pub(crate) async fn append_at<Contents: AsyncRead + Unpin>(
+        &mut self,
+        timestamp: Timestamp,
+        metadata: &Metadata,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        let position = self
+            .segment_writer
+            .append_item_at(timestamp, metadata, contents)
+            .await?;
+        let new_tail_pos = self.segment_writer.write_pos;
+
+        let sync = self.tail_writer.sync_due(new_tail_pos);
+        if sync {
+            self.segment_writer.sync_log_file().await?;
+        }
+        self.tail_writer.commit(new_tail_pos, sync).await?;
+
+        Ok(position)
+    }
+
+    /// Appends an item only if it would be written at `expected_tail`, ie nothing else has been
+    /// appended since the caller last looked at the log.
+    pub async fn append_if<Contents: AsyncRead + Unpin>(
+        &mut self,
+        expected_tail: LogPosition,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        self.append_if_with_metadata(expected_tail, &Metadata::new(), contents)
+            .await
+    }
+
+    /// See `append_if` and `append_with_metadata`.
+    pub async fn append_if_with_metadata<Contents: AsyncRead + Unpin>(
+        &mut self,
+        expected_tail: LogPosition,
+        metadata: &Metadata,
+        contents: &mut Contents,
+    ) -> Result<LogPosition, Error> {
+        if self.segment_writer.write_pos != expected_tail {
+            return Err(Error::TailMoved(self.segment_writer.write_pos));
+        }
+        self.append_with_metadata(metadata, contents).await
+    }
+
+    /// Appends several items which become visible to readers together, or not at all.
+    ///
+    /// The whole batch is written to the same segment.
+    pub async fn append_batch<Contents: AsyncRead + Unpin>(
+        &mut self,
+        items: &mut [Contents],
+    ) -> Result<Vec<LogPosition>, Error> {
+        let positions = self.segment_writer.append_items(items).await?;
+        let new_tail_pos = self.segment_writer.write_pos;
+
+        let sync = self.tail_writer.sync_due(new_tail_pos);
+        if sync {
+            self.segment_writer.sync_log_file().await?;
+        }
+        self.tail_writer.commit(new_tail_pos, sync).await?;
 
-        Ok(old_tail_pos)
+        Ok(positions)
     }
 }
@@ -0,0 +1,51 @@
+use tempdir::TempDir;
+
+#[tokio::test]
+async fn append_if_tail_matches() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+
+    let first = opened
+        .writer
+        .append_if(0, &mut "first".as_bytes())
+        .await
+        .unwrap();
+    assert_eq!(0, first);
+
+    let second = opened
+        .writer
+        .append(&mut "second".as_bytes())
+        .await
+        .unwrap();
+    let tail_pos = match opened
+        .writer
+        .append_if(second, &mut "stale".as_bytes())
+        .await
+    {
+        Err(disklog::writer::Error::TailMoved(pos)) => pos,
+        other => panic!("Expected a conflict, got {:?}", other),
+    };
+    assert!(tail_pos > second);
+
+    let third = opened
+        .writer
+        .append_if(tail_pos, &mut "third".as_bytes())
+        .await
+        .unwrap();
+    assert_eq!(tail_pos, third);
+}
+
+#[tokio::test]
+async fn group_append_if_tail_matches() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    let writer = disklog::group::GroupWriter::new(opened.writer)
+        .await
+        .unwrap();
+
+    let first = writer.append_if(0, &mut "first".as_bytes()).await.unwrap();
+    match writer.append_if(first, &mut "stale".as_bytes()).await {
+        Err(disklog::writer::Error::TailMoved(pos)) => assert!(pos > first),
+        other => panic!("Expected a conflict, got {:?}", other),
+    }
+}
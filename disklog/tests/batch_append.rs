@@ -0,0 +1,115 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tempdir::TempDir;
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+mod common;
+use common::read_all;
+
+/// Contents which can never be read.
+struct FailingContents;
+
+impl AsyncRead for FailingContents {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Err(io::Error::other("Failed to read contents")))
+    }
+}
+
+#[tokio::test]
+async fn append_batch() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+
+    let mut batch = [&b"one"[..], &b"two"[..], &b"three"[..]];
+    let positions = opened.writer.append_batch(&mut batch).await.unwrap();
+    assert_eq!(3, positions.len());
+
+    let items = read_all(&opened.reader_factory).await;
+    assert_eq!(
+        vec![
+            (positions[0], "one".to_string()),
+            (positions[1], "two".to_string()),
+            (positions[2], "three".to_string()),
+        ],
+        items
+    );
+}
+
+#[tokio::test]
+async fn failed_batch_is_discarded() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    let first = opened.writer.append(&mut "first".as_bytes()).await.unwrap();
+
+    let mut batch: [Box<dyn AsyncRead + Unpin>; 2] =
+        [Box::new("lost".as_bytes()), Box::new(FailingContents)];
+    assert!(opened.writer.append_batch(&mut batch).await.is_err());
+    assert_eq!(
+        vec![(first, "first".to_string())],
+        read_all(&opened.reader_factory).await
+    );
+
+    assert_eq!(1, opened.reader_factory.item_count().await.unwrap());
+
+    let second = opened
+        .writer
+        .append(&mut "second".as_bytes())
+        .await
+        .unwrap();
+    let reader = opened.reader_factory.read_from_index(1).await.unwrap();
+    assert_eq!(second, reader.position());
+    drop(opened);
+
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    assert_eq!(
+        vec![(first, "first".to_string()), (second, "second".to_string())],
+        read_all(&opened.reader_factory).await
+    );
+}
+
+#[tokio::test]
+async fn failed_batch_is_removed_from_time_index() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 1,
+        ..Default::default()
+    };
+    let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    opened.writer.append(&mut "first".as_bytes()).await.unwrap();
+
+    // Large enough that both items in the new segment get time index entries
+    let large = vec![b'x'; 64 * 1024];
+    let mut batch: [Box<dyn AsyncRead + Unpin>; 3] = [
+        Box::new(&large[..]),
+        Box::new("lost".as_bytes()),
+        Box::new(FailingContents),
+    ];
+    assert!(opened.writer.append_batch(&mut batch).await.is_err());
+
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let after_batch = std::time::SystemTime::now();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+    // Covers where the second item of the failed batch was
+    let replacement = vec![b'y'; 128 * 1024];
+    let position = opened.writer.append(&mut &replacement[..]).await.unwrap();
+
+    let reader = opened
+        .reader_factory
+        .read_from_time(after_batch)
+        .await
+        .unwrap();
+    let mut item = reader.next(false).await.unwrap().unwrap();
+    assert_eq!(position, item.position());
+    let mut contents = Vec::new();
+    item.read_to_end(&mut contents).await.unwrap();
+    assert_eq!(replacement, contents);
+}
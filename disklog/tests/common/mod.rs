@@ -0,0 +1,57 @@
+// Each test crate only uses some of the helpers
+#![allow(dead_code)]
+
+use std::path::Path;
+
+use tokio::io::AsyncReadExt;
+
+/// Appends each message in turn, returning their positions.
+pub async fn append_all(
+    writer: &mut disklog::writer::Writer,
+    msgs: &[&str],
+) -> Vec<disklog::LogPosition> {
+    let mut positions = Vec::new();
+    for msg in msgs.iter() {
+        positions.push(writer.append(&mut msg.as_bytes()).await.unwrap());
+    }
+    positions
+}
+
+/// Opens the log with `options` and appends each message, returning the log and the positions.
+pub async fn write_log(
+    path: impl AsRef<Path>,
+    options: &disklog::LogOptions,
+    msgs: &[&str],
+) -> (disklog::OpenedLog, Vec<disklog::LogPosition>) {
+    let mut opened = disklog::open_log_with_options(path, options).await.unwrap();
+    let positions = append_all(&mut opened.writer, msgs).await;
+    (opened, positions)
+}
+
+/// Reads items until the end of the log, returning their positions and contents.
+pub async fn read_items(
+    mut reader: disklog::reader::Reader,
+    wait_for_more: bool,
+) -> Vec<(disklog::LogPosition, String)> {
+    let mut items = Vec::new();
+    while let disklog::reader::NextItem::Item(mut item) = reader.next(wait_for_more).await.unwrap()
+    {
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        items.push((item.position(), contents));
+        reader = item.finish();
+    }
+    items
+}
+
+/// Reads every item from the start of the log, returning their positions and contents.
+pub async fn read_all(
+    reader_factory: &disklog::reader::ReaderFactory,
+) -> Vec<(disklog::LogPosition, String)> {
+    read_items(reader_factory.read_from(0).await.unwrap(), false).await
+}
+
+/// The contents of the items read by `read_items` or `read_all`.
+pub fn contents(items: Vec<(disklog::LogPosition, String)>) -> Vec<String> {
+    items.into_iter().map(|(_, contents)| contents).collect()
+}
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const MSGS: [&str; 3] = ["first", "second", "third"];
+
+/// Long enough that a follower which never sees the append fails rather than hangs.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn follower_sees_appends() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 10,
+        ..Default::default()
+    };
+    let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    let first = opened.writer.append(&mut MSGS[0].as_bytes()).await.unwrap();
+
+    // The follower has its own view of the tail, read from the tail file
+    let reader_factory = disklog::open_log_follower(&temp_dir).await.unwrap();
+    let mut reader = reader_factory.read_from(first).await.unwrap();
+
+    let follow = tokio::spawn(async move {
+        let mut items = Vec::new();
+        for _ in MSGS.iter() {
+            let mut item = reader.next(true).await.unwrap().unwrap();
+            let mut contents = String::new();
+            item.read_to_string(&mut contents).await.unwrap();
+            items.push(contents);
+            reader = item.finish();
+        }
+        items
+    });
+
+    // Each item goes in a new segment, which the follower has to find
+    for msg in MSGS[1..].iter() {
+        opened.writer.append(&mut msg.as_bytes()).await.unwrap();
+    }
+
+    let items = tokio::time::timeout(TIMEOUT, follow)
+        .await
+        .expect("Follower to see the appended items")
+        .unwrap();
+    assert_eq!(MSGS.to_vec(), items);
+}
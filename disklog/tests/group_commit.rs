@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::future;
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const APPENDS: usize = 50;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn group_commit() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 256,
+        ..Default::default()
+    };
+
+    let opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    let writer = Arc::new(
+        disklog::group::GroupWriter::new(opened.writer)
+            .await
+            .unwrap(),
+    );
+
+    let appends = (0..APPENDS).map(|i| {
+        let writer = writer.clone();
+        tokio::spawn(async move {
+            let msg = format!("Message {}", i);
+            let position = writer.append(&mut msg.as_bytes()).await.unwrap();
+            (position, msg)
+        })
+    });
+    let appended: HashMap<u64, String> = future::join_all(appends)
+        .await
+        .into_iter()
+        .map(|result| result.unwrap())
+        .collect();
+    assert_eq!(
+        APPENDS,
+        appended.len(),
+        "Every append needs its own position"
+    );
+
+    let mut reader = opened.reader_factory.read_from(0).await.unwrap();
+    let mut read = 0;
+    while let disklog::reader::NextItem::Item(mut item) = reader.next(false).await.unwrap() {
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(appended[&item.position()], contents);
+        read += 1;
+        reader = item.finish();
+    }
+    assert_eq!(APPENDS, read);
+}
@@ -0,0 +1,42 @@
+use std::time::{Duration, Instant};
+
+use tempdir::TempDir;
+
+#[tokio::test]
+async fn open_waits_for_lock() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        std::mem::drop(opened);
+    });
+
+    let options = disklog::LogOptions {
+        lock_timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn open_times_out() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+
+    let _opened = disklog::open_log(&temp_dir).await.unwrap();
+
+    let timeout = Duration::from_millis(200);
+    let options = disklog::LogOptions {
+        lock_timeout: Some(timeout),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    match disklog::open_log_with_options(&temp_dir, &options).await {
+        Ok(_) => panic!("Opened log for writing twice!"),
+        Err(disklog::OpenError::AlreadyOpen) => {}
+        Err(_) => panic!("Second open failed for wrong reason"),
+    };
+    assert!(start.elapsed() >= timeout);
+}
@@ -0,0 +1,74 @@
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+#[tokio::test]
+async fn read_item_metadata() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+
+    let mut metadata = disklog::Metadata::new();
+    metadata.insert("content-type", "text/plain");
+    metadata.insert("event", "created");
+    opened
+        .writer
+        .append_with_metadata(&metadata, &mut "Hello World".as_bytes())
+        .await
+        .unwrap();
+    opened
+        .writer
+        .append(&mut "No metadata".as_bytes())
+        .await
+        .unwrap();
+    drop(opened);
+
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    let reader = opened.reader_factory.read_from(0).await.unwrap();
+
+    let mut item = reader.next(false).await.unwrap().unwrap();
+    assert_eq!(&metadata, item.metadata());
+    assert_eq!(Some(&b"created"[..]), item.metadata().get("event"));
+    assert_eq!(11, item.len());
+    let mut contents = String::new();
+    item.read_to_string(&mut contents).await.unwrap();
+    assert_eq!("Hello World", contents);
+
+    let mut item = item.finish().next(false).await.unwrap().unwrap();
+    assert!(item.metadata().is_empty());
+    let mut contents = String::new();
+    item.read_to_string(&mut contents).await.unwrap();
+    assert_eq!("No metadata", contents);
+}
+
+#[tokio::test]
+async fn metadata_too_large() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+
+    let mut metadata = disklog::Metadata::new();
+    metadata.insert("big", vec![0u8; disklog::MAX_METADATA_SIZE]);
+    match opened
+        .writer
+        .append_with_metadata(&metadata, &mut "Hello World".as_bytes())
+        .await
+    {
+        Err(disklog::writer::Error::MetadataTooLarge) => {}
+        other => panic!("Expected metadata to be too large, got {:?}", other),
+    }
+}
+
+#[test]
+fn encode_and_decode_metadata() {
+    let mut metadata = disklog::Metadata::new();
+    metadata.insert("a", "1");
+    metadata.insert("b", "2");
+    metadata.insert("a", "3");
+    assert_eq!(2, metadata.len());
+    assert_eq!(Some(&b"3"[..]), metadata.get("a"));
+
+    let encoded = metadata.encode().unwrap();
+    assert_eq!(Some(metadata), disklog::Metadata::decode(&encoded));
+    assert_eq!(
+        None,
+        disklog::Metadata::decode(&encoded[..encoded.len() - 1])
+    );
+}
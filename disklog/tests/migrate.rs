@@ -0,0 +1,315 @@
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const MSGS: [&str; 2] = ["Hello World", "Another Message"];
+
+fn item_checksum(pos: u64, len: u32) -> u16 {
+    let pos_bytes = pos.to_le_bytes();
+    let len_bytes = len.to_le_bytes();
+    u16::from_le_bytes([pos_bytes[0], pos_bytes[1]])
+        ^ u16::from_le_bytes([pos_bytes[2], pos_bytes[3]])
+        ^ u16::from_le_bytes([pos_bytes[4], pos_bytes[5]])
+        ^ u16::from_le_bytes([pos_bytes[6], pos_bytes[7]])
+        ^ u16::from_le_bytes([len_bytes[0], len_bytes[1]])
+        ^ u16::from_le_bytes([len_bytes[2], len_bytes[3]])
+}
+
+fn file_header(magic: &[u8; 4], version: u32) -> Vec<u8> {
+    let mut header = magic.to_vec();
+    header.extend_from_slice(&version.to_be_bytes());
+    header.extend_from_slice(&0u64.to_be_bytes());
+    header.extend_from_slice(&0u32.to_be_bytes());
+    header
+}
+
+fn tail_copies(tail_pos: u64) -> Vec<u8> {
+    let mut tail = Vec::new();
+    for _ in 0..3 {
+        tail.extend_from_slice(&tail_pos.to_be_bytes());
+    }
+    tail
+}
+
+/// Writes a log in the original headerless format, without contents checksums.
+fn write_v0_log(path: &std::path::Path) {
+    let mut log = Vec::new();
+    for msg in MSGS.iter() {
+        let pos = log.len() as u64;
+        let len = msg.len() as u32;
+        log.extend_from_slice(&item_checksum(pos, len).to_be_bytes());
+        log.extend_from_slice(&len.to_be_bytes());
+        log.extend_from_slice(msg.as_bytes());
+    }
+
+    std::fs::create_dir_all(path).unwrap();
+    std::fs::write(path.join("tail"), tail_copies(log.len() as u64)).unwrap();
+    std::fs::write(path.join("log"), log).unwrap();
+}
+
+/// Items from versions 1 and 2, with a contents checksum but no metadata.
+fn v2_item(pos: u64, msg: &str) -> Vec<u8> {
+    let len = msg.len() as u32;
+    let mut item = Vec::new();
+    item.extend_from_slice(&item_checksum(pos, len).to_be_bytes());
+    item.extend_from_slice(&len.to_be_bytes());
+    item.extend_from_slice(&crc32c::crc32c(msg.as_bytes()).to_be_bytes());
+    item.extend_from_slice(msg.as_bytes());
+    item
+}
+
+/// Writes a version 1 log, which kept every item in a single file.
+fn write_v1_log(path: &std::path::Path) {
+    let mut items = Vec::new();
+    for msg in MSGS.iter() {
+        items.extend(v2_item(items.len() as u64, msg));
+    }
+
+    let mut tail = file_header(b"DTAL", 1);
+    tail.extend(tail_copies(items.len() as u64));
+    let mut log = file_header(b"DLOG", 1);
+    log.extend(items);
+
+    std::fs::create_dir_all(path).unwrap();
+    std::fs::write(path.join("tail"), tail).unwrap();
+    std::fs::write(path.join("log"), log).unwrap();
+}
+
+/// Writes a version 2 log with each item in its own segment.
+fn write_v2_log(path: &std::path::Path) {
+    std::fs::create_dir_all(path).unwrap();
+
+    let mut pos = 0;
+    for msg in MSGS.iter() {
+        let item = v2_item(pos, msg);
+        let mut segment = file_header(b"DLOG", 2);
+        segment.extend_from_slice(&item);
+        std::fs::write(path.join(format!("{:020}.log", pos)), segment).unwrap();
+        pos += item.len() as u64;
+    }
+
+    let mut tail = file_header(b"DTAL", 2);
+    tail.extend(tail_copies(pos));
+    std::fs::write(path.join("tail"), tail).unwrap();
+}
+
+/// Writes a version 3 log, where items have metadata but no timestamp.
+fn write_v3_log(path: &std::path::Path, metadata: &disklog::Metadata) {
+    let metadata = metadata.encode().unwrap();
+    let mut items = Vec::new();
+    for msg in MSGS.iter() {
+        let mut record = (metadata.len() as u16).to_be_bytes().to_vec();
+        record.extend_from_slice(&metadata);
+        record.extend_from_slice(msg.as_bytes());
+
+        let pos = items.len() as u64;
+        let len = record.len() as u32;
+        items.extend_from_slice(&item_checksum(pos, len).to_be_bytes());
+        items.extend_from_slice(&len.to_be_bytes());
+        items.extend_from_slice(&crc32c::crc32c(&record).to_be_bytes());
+        items.extend(record);
+    }
+
+    let mut tail = file_header(b"DTAL", 3);
+    tail.extend(tail_copies(items.len() as u64));
+    let mut segment = file_header(b"DLOG", 3);
+    segment.extend(items);
+
+    std::fs::create_dir_all(path).unwrap();
+    std::fs::write(path.join("tail"), tail).unwrap();
+    std::fs::write(path.join("00000000000000000000.log"), segment).unwrap();
+}
+
+/// Writes a version 4 log, which stored the tail position as three copies.
+fn write_v4_log(path: &std::path::Path) {
+    let mut items = Vec::new();
+    for msg in MSGS.iter() {
+        let mut record = 0u64.to_be_bytes().to_vec();
+        record.extend_from_slice(&0u16.to_be_bytes());
+        record.extend_from_slice(msg.as_bytes());
+
+        let pos = items.len() as u64;
+        let len = record.len() as u32;
+        items.extend_from_slice(&item_checksum(pos, len).to_be_bytes());
+        items.extend_from_slice(&len.to_be_bytes());
+        items.extend_from_slice(&crc32c::crc32c(&record).to_be_bytes());
+        items.extend(record);
+    }
+
+    let mut tail = file_header(b"DTAL", 4);
+    tail.extend(tail_copies(items.len() as u64));
+    let mut segment = file_header(b"DLOG", 4);
+    segment.extend(items);
+
+    std::fs::create_dir_all(path).unwrap();
+    std::fs::write(path.join("tail"), tail).unwrap();
+    std::fs::write(path.join("00000000000000000000.log"), segment).unwrap();
+    std::fs::write(path.join("offsetindex"), file_header(b"DOFF", 4)).unwrap();
+}
+
+/// Writes a version 5 log, which had no item trailers.
+fn write_v5_log(path: &std::path::Path) {
+    let mut items = Vec::new();
+    for msg in MSGS.iter() {
+        let mut record = 0u64.to_be_bytes().to_vec();
+        record.extend_from_slice(&0u16.to_be_bytes());
+        record.extend_from_slice(msg.as_bytes());
+
+        let pos = items.len() as u64;
+        let len = record.len() as u32;
+        items.extend_from_slice(&item_checksum(pos, len).to_be_bytes());
+        items.extend_from_slice(&len.to_be_bytes());
+        items.extend_from_slice(&crc32c::crc32c(&record).to_be_bytes());
+        items.extend(record);
+    }
+
+    // Generation 0 in both tail records
+    let mut tail = file_header(b"DTAL", 5);
+    for _ in 0..2 {
+        let mut record = 0u64.to_be_bytes().to_vec();
+        record.extend_from_slice(&(items.len() as u64).to_be_bytes());
+        let checksum = crc32c::crc32c(&record);
+        tail.extend(record);
+        tail.extend_from_slice(&checksum.to_be_bytes());
+    }
+    let mut segment = file_header(b"DLOG", 5);
+    segment.extend(items);
+
+    std::fs::create_dir_all(path).unwrap();
+    std::fs::write(path.join("tail"), tail).unwrap();
+    std::fs::write(path.join("00000000000000000000.log"), segment).unwrap();
+    std::fs::write(path.join("offsetindex"), file_header(b"DOFF", 5)).unwrap();
+}
+
+async fn assert_migrated(log_path: &std::path::Path, from_version: u32) {
+    assert_migrated_with_metadata(log_path, from_version, &disklog::Metadata::new()).await
+}
+
+async fn assert_migrated_with_metadata(
+    log_path: &std::path::Path,
+    from_version: u32,
+    metadata: &disklog::Metadata,
+) {
+    match disklog::open_log(log_path).await {
+        Err(disklog::OpenError::UnsupportedVersion(version)) => assert_eq!(from_version, version),
+        Err(_) => panic!("Open failed for wrong reason"),
+        Ok(_) => panic!("Opened a log in an old format"),
+    };
+
+    let migration = disklog::migrate::migrate_log(log_path)
+        .await
+        .unwrap()
+        .expect("Log should need migrating");
+    assert_eq!(from_version, migration.from_version);
+    assert_eq!(MSGS.len() as u64, migration.items);
+
+    assert!(
+        disklog::migrate::migrate_log(log_path)
+            .await
+            .unwrap()
+            .is_none(),
+        "Log should already be migrated"
+    );
+
+    let opened = disklog::open_log(log_path).await.unwrap();
+    let mut reader = opened.reader_factory.read_from(0).await.unwrap();
+    for msg in MSGS.iter() {
+        let mut item = reader.next(false).await.unwrap().unwrap();
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(*msg, contents);
+        assert_eq!(metadata, item.metadata());
+        assert_eq!(std::time::UNIX_EPOCH, item.timestamp());
+        reader = item.finish();
+    }
+    assert!(reader.next(false).await.unwrap().is_end());
+}
+
+#[tokio::test]
+async fn migrate_v0_log() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let log_path = temp_dir.path().join("topic");
+    write_v0_log(&log_path);
+
+    assert_migrated(&log_path, 0).await;
+
+    let entries = std::fs::read_dir(temp_dir.path()).unwrap().count();
+    assert_eq!(
+        1, entries,
+        "Staging and backup directories should be removed"
+    );
+}
+
+#[tokio::test]
+async fn migrate_v1_log() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let log_path = temp_dir.path().join("topic");
+    write_v1_log(&log_path);
+
+    assert_migrated(&log_path, 1).await;
+}
+
+#[tokio::test]
+async fn migrate_v2_log() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let log_path = temp_dir.path().join("topic");
+    write_v2_log(&log_path);
+
+    assert_migrated(&log_path, 2).await;
+}
+
+#[tokio::test]
+async fn migrate_v3_log() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let log_path = temp_dir.path().join("topic");
+    let mut metadata = disklog::Metadata::new();
+    metadata.insert("event", "created");
+    write_v3_log(&log_path, &metadata);
+
+    assert_migrated_with_metadata(&log_path, 3, &metadata).await;
+}
+
+#[tokio::test]
+async fn migrate_v4_log() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let log_path = temp_dir.path().join("topic");
+    write_v4_log(&log_path);
+
+    assert_migrated(&log_path, 4).await;
+}
+
+#[tokio::test]
+async fn migrate_v5_log() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let log_path = temp_dir.path().join("topic");
+    write_v5_log(&log_path);
+
+    assert_migrated(&log_path, 5).await;
+}
+
+#[tokio::test]
+async fn corrupt_contents_fail_migration() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let log_path = temp_dir.path().join("topic");
+    write_v5_log(&log_path);
+
+    // Flip a byte in the contents of the second item
+    let segment_path = log_path.join("00000000000000000000.log");
+    let mut segment = std::fs::read(&segment_path).unwrap();
+    let last = segment.len() - 1;
+    segment[last] ^= 0xff;
+    std::fs::write(&segment_path, &segment).unwrap();
+
+    let second_pos = (2 + 4 + 4 + 8 + 2 + MSGS[0].len()) as u64;
+    match disklog::migrate::migrate_log(&log_path).await {
+        Err(disklog::migrate::Error::InvalidPayloadChecksum(pos)) => assert_eq!(second_pos, pos),
+        Err(e) => panic!("Migration failed for the wrong reason: {}", e),
+        Ok(_) => panic!("Migrated a log with corrupt contents"),
+    }
+
+    // The old log is left untouched
+    assert_eq!(segment, std::fs::read(&segment_path).unwrap());
+    match disklog::open_log(&log_path).await {
+        Err(disklog::OpenError::UnsupportedVersion(version)) => assert_eq!(5, version),
+        _ => panic!("Old log should still need migrating"),
+    }
+}
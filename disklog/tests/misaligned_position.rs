@@ -0,0 +1,30 @@
+use tempdir::TempDir;
+
+#[tokio::test]
+async fn misaligned_position() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+
+    let first = opened.writer.append(&mut "first".as_bytes()).await.unwrap();
+    let second = opened
+        .writer
+        .append(&mut "second".as_bytes())
+        .await
+        .unwrap();
+    let tail = opened
+        .reader_factory
+        .read_from_index(2)
+        .await
+        .unwrap()
+        .position();
+
+    for position in [first, second, tail].iter() {
+        assert!(opened.reader_factory.read_from(*position).await.is_ok());
+    }
+    for position in [first + 1, second - 1, second + 4, tail + 1].iter() {
+        match opened.reader_factory.read_from(*position).await {
+            Err(disklog::reader::Error::MisalignedPosition) => {}
+            _ => panic!("Expected {} to be misaligned", position),
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const MSG: &str = "Hello World";
+
+#[tokio::test]
+async fn payload_checksum() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+
+    {
+        let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+        opened.writer.append(&mut MSG.as_bytes()).await.unwrap();
+
+        let mut item = opened
+            .reader_factory
+            .read_from(0)
+            .await
+            .unwrap()
+            .next(false)
+            .await
+            .unwrap()
+            .unwrap();
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(MSG, contents);
+    }
+
+    // Flip a bit in the middle of the item contents, before the 6 byte trailer
+    {
+        let mut log_file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(temp_dir.path().join("00000000000000000000.log"))
+            .unwrap();
+        let len = log_file.metadata().unwrap().len();
+        log_file.seek(SeekFrom::Start(len - 6 - 4)).unwrap();
+        log_file.write_all(b"X").unwrap();
+    }
+
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    let mut item = opened
+        .reader_factory
+        .read_from(0)
+        .await
+        .unwrap()
+        .next(false)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut contents = Vec::new();
+    let error = item.read_to_end(&mut contents).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+    match error
+        .into_inner()
+        .unwrap()
+        .downcast::<disklog::reader::Error>()
+    {
+        Ok(e) => match *e {
+            disklog::reader::Error::InvalidPayloadChecksum => {}
+            _ => panic!("Failed for the wrong reason"),
+        },
+        Err(_) => panic!("Expected a reader error"),
+    }
+}
+
+#[tokio::test]
+async fn empty_item_metadata_checksum() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+
+    {
+        let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+        let mut metadata = disklog::Metadata::new();
+        metadata.insert("event", "created");
+        opened
+            .writer
+            .append_with_metadata(&metadata, &mut "".as_bytes())
+            .await
+            .unwrap();
+    }
+
+    // Change the last byte of the metadata, which the item has nothing after but its trailer
+    {
+        let mut log_file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(temp_dir.path().join("00000000000000000000.log"))
+            .unwrap();
+        let len = log_file.metadata().unwrap().len();
+        log_file.seek(SeekFrom::Start(len - 6 - 1)).unwrap();
+        log_file.write_all(b"X").unwrap();
+    }
+
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    let mut item = opened
+        .reader_factory
+        .read_from(0)
+        .await
+        .unwrap()
+        .next(false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(item.is_empty());
+    let mut contents = Vec::new();
+    let error = item.read_to_end(&mut contents).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, error.kind());
+}
+
+#[tokio::test]
+async fn truncated_contents() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    let contents = vec![b'x'; 1000];
+    opened
+        .writer
+        .append(&mut contents.as_slice())
+        .await
+        .unwrap();
+
+    // Cut the item off half way through its contents, after the 20 byte file header, 10 byte
+    // item header, timestamp and metadata length
+    let log_file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(temp_dir.path().join("00000000000000000000.log"))
+        .unwrap();
+    log_file.set_len(20 + 10 + 8 + 2 + 500).unwrap();
+
+    let mut item = opened
+        .reader_factory
+        .read_from(0)
+        .await
+        .unwrap()
+        .next(false)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut read = Vec::new();
+    let error = item.read_to_end(&mut read).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::UnexpectedEof, error.kind());
+    assert_eq!(500, read.len());
+}
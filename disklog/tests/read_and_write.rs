@@ -94,10 +94,10 @@ async fn read_and_write() {
     let disklog::OpenedLog {
         writer,
         reader_factory,
-        recovered,
+        recovery,
     } = disklog::open_log(&temp_dir).await.unwrap();
 
-    assert_eq!(recovered, false);
+    assert!(recovery.is_none());
 
     let first_barrier = Barrier::new(3);
     let last_barrier = Barrier::new(3);
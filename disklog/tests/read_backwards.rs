@@ -0,0 +1,81 @@
+use futures::TryStreamExt;
+use tempdir::TempDir;
+
+const MSGS: [&str; 4] = ["Hello World", "Another Message", "Third Message", "Fourth"];
+
+#[tokio::test]
+async fn read_backwards() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    // Small segments so reading crosses from one segment into the one before
+    let options = disklog::LogOptions {
+        segment_size: 40,
+        ..Default::default()
+    };
+    let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    let mut positions = Vec::new();
+    for msg in MSGS.iter() {
+        positions.push(opened.writer.append(&mut msg.as_bytes()).await.unwrap());
+    }
+    let tail_pos = opened.reader_factory.tail_position();
+
+    let mut reader = opened
+        .reader_factory
+        .read_backwards_from(tail_pos)
+        .await
+        .unwrap();
+    for (seq, msg) in MSGS.iter().enumerate().rev() {
+        let item = reader.next().await.unwrap().unwrap();
+        assert_eq!(positions[seq], item.position);
+        assert_eq!(seq as u64, item.seq);
+        assert_eq!(msg.as_bytes(), &item.contents[..]);
+        assert_eq!(positions[seq], reader.position());
+    }
+    assert!(reader.next().await.unwrap().is_none());
+
+    // From the middle of the log, stopping after one item
+    let items: Vec<disklog::reader::StreamItem> = opened
+        .reader_factory
+        .read_backwards_from(positions[2])
+        .await
+        .unwrap()
+        .into_stream(Some(1))
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(1, items.len());
+    assert_eq!(positions[1], items[0].position);
+
+    match opened.reader_factory.read_backwards_from(1).await {
+        Err(disklog::reader::Error::MisalignedPosition) => {}
+        _ => panic!("Should not read backwards from inside an item"),
+    }
+}
+
+#[tokio::test]
+async fn read_backwards_corrupt_trailer() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    opened.writer.append(&mut MSGS[0].as_bytes()).await.unwrap();
+    let tail_pos = opened.reader_factory.tail_position();
+    std::mem::drop(opened);
+
+    // Damage the length in the trailer at the end of the segment
+    let segment_path = temp_dir.path().join("00000000000000000000.log");
+    let mut segment = std::fs::read(&segment_path).unwrap();
+    let len = segment.len();
+    segment[len - 1] ^= 0xff;
+    std::fs::write(&segment_path, segment).unwrap();
+
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    let mut reader = opened
+        .reader_factory
+        .read_backwards_from(tail_pos)
+        .await
+        .unwrap();
+    match reader.next().await {
+        Err(disklog::reader::Error::InvalidItemChecksum) => {}
+        _ => panic!("Should fail on the damaged trailer"),
+    }
+}
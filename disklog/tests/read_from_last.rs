@@ -0,0 +1,35 @@
+use tempdir::TempDir;
+
+mod common;
+
+const MSGS: [&str; 3] = ["Hello World", "Another Message", "Third Message"];
+
+async fn read_all(reader: disklog::reader::Reader) -> Vec<String> {
+    common::contents(common::read_items(reader, false).await)
+}
+
+#[tokio::test]
+async fn read_from_last() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    assert_eq!(0, opened.reader_factory.tail_position());
+
+    let positions = common::append_all(&mut opened.writer, &MSGS).await;
+    let factory = &opened.reader_factory;
+    assert!(factory.tail_position() > positions[2]);
+
+    let reader = factory.read_from_last(2).await.unwrap();
+    assert_eq!(positions[1], reader.position());
+    assert_eq!(1, reader.seq());
+    assert_eq!(MSGS[1..], read_all(reader).await[..]);
+
+    // More than there are starts from the first item
+    let reader = factory.read_from_last(10).await.unwrap();
+    assert_eq!(positions[0], reader.position());
+
+    // Zero starts at the tail and only sees new items
+    let reader = factory.read_from_last(0).await.unwrap();
+    assert_eq!(factory.tail_position(), reader.position());
+    opened.writer.append(&mut b"New".as_ref()).await.unwrap();
+    assert_eq!(vec!["New".to_string()], read_all(reader).await);
+}
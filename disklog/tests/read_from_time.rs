@@ -0,0 +1,69 @@
+use std::time::{Duration, SystemTime};
+
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+async fn read_first(
+    reader_factory: &disklog::reader::ReaderFactory,
+    time: SystemTime,
+) -> Option<String> {
+    let reader = reader_factory.read_from_time(time).await.unwrap();
+    match reader.next(false).await.unwrap() {
+        disklog::reader::NextItem::Item(mut item) => {
+            let mut contents = String::new();
+            item.read_to_string(&mut contents).await.unwrap();
+            Some(contents)
+        }
+        disklog::reader::NextItem::End(_) => None,
+    }
+}
+
+#[tokio::test]
+async fn read_from_time() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 40,
+        ..Default::default()
+    };
+
+    let mut times = Vec::new();
+    {
+        let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+            .await
+            .unwrap();
+        for i in 0..5 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            times.push(SystemTime::now());
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            opened
+                .writer
+                .append(&mut format!("Message {}", i).as_bytes())
+                .await
+                .unwrap();
+        }
+    }
+
+    let opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    for (i, time) in times.iter().enumerate() {
+        assert_eq!(
+            Some(format!("Message {}", i)),
+            read_first(&opened.reader_factory, *time).await
+        );
+    }
+
+    let item = opened
+        .reader_factory
+        .read_from(0)
+        .await
+        .unwrap()
+        .next(false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(item.timestamp() > times[0] && item.timestamp() < times[1]);
+
+    let later = SystemTime::now() + Duration::from_secs(60);
+    assert_eq!(None, read_first(&opened.reader_factory, later).await);
+}
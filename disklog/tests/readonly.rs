@@ -0,0 +1,43 @@
+use tempdir::TempDir;
+
+mod common;
+
+const MSGS: [&str; 3] = ["first", "second", "third"];
+
+/// Reads every item, waiting for more at the tail.
+async fn read_all(reader_factory: &disklog::reader::ReaderFactory) -> Vec<String> {
+    let reader = reader_factory.read_from(0).await.unwrap();
+    common::contents(common::read_items(reader, true).await)
+}
+
+#[tokio::test]
+async fn read_alongside_writer() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    for msg in MSGS[..2].iter() {
+        opened.writer.append(&mut msg.as_bytes()).await.unwrap();
+    }
+
+    let reader_factory = disklog::open_log_readonly(&temp_dir).await.unwrap();
+    assert_eq!(MSGS[..2].to_vec(), read_all(&reader_factory).await);
+
+    // Items appended after opening are not seen, waiting for more ends at the tail
+    opened.writer.append(&mut MSGS[2].as_bytes()).await.unwrap();
+    assert_eq!(MSGS[..2].to_vec(), read_all(&reader_factory).await);
+
+    let reader_factory = disklog::open_log_readonly(&temp_dir).await.unwrap();
+    assert_eq!(MSGS.to_vec(), read_all(&reader_factory).await);
+
+    // Opening read-only does not stop the log being opened for writing
+    drop(opened);
+    disklog::open_log(&temp_dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn readonly_missing_log() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    assert!(disklog::open_log_readonly(temp_dir.path().join("missing"))
+        .await
+        .is_err());
+    assert!(!temp_dir.path().join("missing").exists());
+}
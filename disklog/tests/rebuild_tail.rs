@@ -0,0 +1,99 @@
+use std::io::Write;
+
+use tempdir::TempDir;
+
+mod common;
+use common::read_all;
+
+const MSGS: [&str; 4] = ["first", "second", "third", "fourth"];
+
+fn rebuild_options() -> disklog::LogOptions {
+    disklog::LogOptions {
+        segment_size: 40,
+        rebuild_tail: true,
+        ..Default::default()
+    }
+}
+
+/// Writes the messages and closes the log, returning their positions.
+async fn write_log(temp_dir: &TempDir) -> Vec<u64> {
+    common::write_log(temp_dir, &rebuild_options(), &MSGS)
+        .await
+        .1
+}
+
+#[tokio::test]
+async fn rebuild_lost_tail() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let positions = write_log(&temp_dir).await;
+    std::fs::remove_file(temp_dir.path().join("tail")).unwrap();
+
+    assert!(matches!(
+        disklog::open_log(&temp_dir).await,
+        Err(disklog::OpenError::CorruptTailPosition)
+    ));
+
+    {
+        let opened = disklog::open_log_with_options(&temp_dir, &rebuild_options())
+            .await
+            .unwrap();
+        let recovery = opened.recovery.expect("Tail to be rebuilt");
+        assert!(recovery.tail_rebuilt);
+        assert_eq!(0, recovery.discarded_bytes);
+
+        let expected: Vec<(u64, String)> = positions
+            .iter()
+            .copied()
+            .zip(MSGS.iter().map(|msg| msg.to_string()))
+            .collect();
+        assert_eq!(expected, read_all(&opened.reader_factory).await);
+    }
+
+    // The rebuilt tail file is used from then on
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    assert!(opened.recovery.is_none());
+}
+
+#[tokio::test]
+async fn rebuild_corrupt_tail_discards_torn_write() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    write_log(&temp_dir).await;
+
+    // Both tail records are damaged
+    let tail_path = temp_dir.path().join("tail");
+    let mut tail = std::fs::read(&tail_path).unwrap();
+    for byte in tail[20..].iter_mut() {
+        *byte = !*byte;
+    }
+    std::fs::write(&tail_path, tail).unwrap();
+
+    // Part of an item written after the last one
+    let last_segment = std::fs::read_dir(&temp_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "log"))
+        .max()
+        .unwrap();
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(last_segment)
+        .unwrap()
+        .write_all(b"torn")
+        .unwrap();
+
+    assert!(matches!(
+        disklog::open_log(&temp_dir).await,
+        Err(disklog::OpenError::CorruptTailPosition)
+    ));
+
+    let mut opened = disklog::open_log_with_options(&temp_dir, &rebuild_options())
+        .await
+        .unwrap();
+    let recovery = opened.recovery.expect("Tail to be rebuilt");
+    assert!(recovery.tail_rebuilt);
+    assert_eq!(4, recovery.discarded_bytes);
+
+    let fifth = opened.writer.append(&mut "fifth".as_bytes()).await.unwrap();
+    assert_eq!(recovery.tail_pos, fifth);
+    assert_eq!(MSGS.len() + 1, read_all(&opened.reader_factory).await.len());
+}
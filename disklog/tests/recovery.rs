@@ -0,0 +1,136 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use disklog::reader::{Reader, Recovered};
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const MSGS: [&str; 4] = ["first", "second", "third", "fourth"];
+
+/// The size of the segment file header.
+const FILE_HEADER_SIZE: u64 = 20;
+
+fn corrupt_header(temp_dir: &TempDir, position: u64) {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(temp_dir.path().join("00000000000000000000.log"))
+        .unwrap();
+    file.seek(SeekFrom::Start(FILE_HEADER_SIZE + position))
+        .unwrap();
+    file.write_all(&[0xff, 0xff]).unwrap();
+}
+
+enum Read {
+    Item(u64, String),
+    Skipped(std::ops::Range<u64>),
+}
+
+async fn read_recovering(mut reader: Reader) -> Vec<Read> {
+    let mut read = Vec::new();
+    loop {
+        match reader.next_recovering(false).await.unwrap() {
+            Recovered::Item(mut item) => {
+                let mut contents = String::new();
+                item.read_to_string(&mut contents).await.unwrap();
+                read.push(Read::Item(item.position(), contents));
+                reader = item.finish();
+            }
+            Recovered::Skipped(range, next) => {
+                read.push(Read::Skipped(range));
+                reader = next;
+            }
+            Recovered::End(_) => return read,
+        }
+    }
+}
+
+#[tokio::test]
+async fn skip_corrupt_item() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    let mut positions = Vec::new();
+    for msg in MSGS.iter() {
+        positions.push(opened.writer.append(&mut msg.as_bytes()).await.unwrap());
+    }
+
+    corrupt_header(&temp_dir, positions[1]);
+
+    let reader = opened.reader_factory.read_from(0).await.unwrap();
+    match read_recovering(reader).await.as_slice() {
+        [Read::Item(first, first_contents), Read::Skipped(skipped), Read::Item(third, third_contents), Read::Item(fourth, fourth_contents)] =>
+        {
+            assert_eq!((positions[0], "first"), (*first, first_contents.as_str()));
+            assert_eq!(positions[1]..positions[2], *skipped);
+            assert_eq!((positions[2], "third"), (*third, third_contents.as_str()));
+            assert_eq!(
+                (positions[3], "fourth"),
+                (*fourth, fourth_contents.as_str())
+            );
+        }
+        _ => panic!("Unexpected items read"),
+    }
+
+    // The reader after the skipped range knows which item it is at
+    let reader = opened.reader_factory.read_from(positions[1]).await.unwrap();
+    match reader.next_recovering(false).await.unwrap() {
+        Recovered::Skipped(_, reader) => assert_eq!(2, reader.seq()),
+        _ => panic!("Expected the corrupt item to be skipped"),
+    }
+}
+
+#[tokio::test]
+async fn skip_to_tail() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    let mut positions = Vec::new();
+    for msg in MSGS.iter() {
+        positions.push(opened.writer.append(&mut msg.as_bytes()).await.unwrap());
+    }
+
+    corrupt_header(&temp_dir, positions[3]);
+
+    let reader = opened.reader_factory.read_from(positions[2]).await.unwrap();
+    match read_recovering(reader).await.as_slice() {
+        [Read::Item(third, _), Read::Skipped(skipped)] => {
+            assert_eq!(positions[2], *third);
+            assert_eq!(positions[3], skipped.start);
+            assert_eq!(
+                opened.writer.append(&mut "fifth".as_bytes()).await.unwrap(),
+                skipped.end
+            );
+        }
+        _ => panic!("Unexpected items read"),
+    }
+}
+
+#[tokio::test]
+async fn salvage_after_reopening() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    let mut positions = Vec::new();
+    for msg in MSGS.iter() {
+        positions.push(opened.writer.append(&mut msg.as_bytes()).await.unwrap());
+    }
+    std::mem::drop(opened);
+
+    // The offset index is rebuilt from the item headers when it is lost
+    corrupt_header(&temp_dir, positions[1]);
+    std::fs::remove_file(temp_dir.path().join("offsetindex")).unwrap();
+
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    assert_eq!(
+        Some(positions[1]),
+        opened.recovery.and_then(|recovery| recovery.unindexed_from)
+    );
+
+    let reader = opened.reader_factory.read_from(0).await.unwrap();
+    match read_recovering(reader).await.as_slice() {
+        [Read::Item(first, _), Read::Skipped(skipped), Read::Item(third, _), Read::Item(fourth, _)] =>
+        {
+            assert_eq!(positions[0], *first);
+            assert_eq!(positions[1]..positions[2], *skipped);
+            assert_eq!(positions[2], *third);
+            assert_eq!(positions[3], *fourth);
+        }
+        _ => panic!("Unexpected items read"),
+    }
+}
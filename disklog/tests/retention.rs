@@ -0,0 +1,121 @@
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+mod common;
+
+const MSGS: [&str; 6] = ["one", "two", "three", "four", "five", "six"];
+
+#[tokio::test]
+async fn retain_max_items() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        // One item per segment
+        segment_size: 10,
+        retention: disklog::RetentionPolicy {
+            max_items: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let (opened, positions) = common::write_log(&temp_dir, &options, &MSGS).await;
+
+    // Retention is applied before starting the segment for the last item
+    assert_eq!(positions[2], opened.reader_factory.first_position());
+
+    match opened.reader_factory.read_from(0).await {
+        Err(disklog::reader::Error::PositionRemoved(first)) => assert_eq!(positions[2], first),
+        _ => panic!("Should not be able to read removed items"),
+    }
+
+    let mut reader = opened.reader_factory.read_from(positions[2]).await.unwrap();
+    for msg in MSGS[2..].iter() {
+        let mut item = reader.next(false).await.unwrap().unwrap();
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(*msg, contents);
+        reader = item.finish();
+    }
+
+    // Item counts are recovered when reopening
+    std::mem::drop(opened);
+    let opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    assert_eq!(positions[3], opened.reader_factory.first_position());
+}
+
+#[tokio::test]
+async fn retain_max_bytes() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 10,
+        retention: disklog::RetentionPolicy {
+            max_bytes: Some(0),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let (opened, positions) = common::write_log(&temp_dir, &options, &MSGS).await;
+
+    // Only the segment being written to is kept
+    assert_eq!(positions[5], opened.reader_factory.first_position());
+    let segment_files = std::fs::read_dir(&temp_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("log".as_ref()))
+        .count();
+    assert_eq!(1, segment_files);
+}
+
+#[tokio::test]
+async fn retention_trims_indexes() {
+    let index_sizes = |path: &TempDir| {
+        ["offsetindex", "timeindex"]
+            .iter()
+            .map(|name| std::fs::metadata(path.path().join(name)).unwrap().len())
+            .collect::<Vec<_>>()
+    };
+
+    let untrimmed_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 10,
+        ..Default::default()
+    };
+    common::write_log(&untrimmed_dir, &options, &MSGS).await;
+
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 10,
+        retention: disklog::RetentionPolicy {
+            max_items: Some(3),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (opened, positions) = common::write_log(&temp_dir, &options, &MSGS).await;
+
+    let sizes = index_sizes(&temp_dir);
+    let untrimmed_sizes = index_sizes(&untrimmed_dir);
+    assert!(sizes[0] < untrimmed_sizes[0]);
+    assert!(sizes[1] < untrimmed_sizes[1]);
+
+    // The items which are left keep their sequence numbers
+    let reader_factory = opened.reader_factory;
+    assert_eq!(6, reader_factory.item_count().await.unwrap());
+    let reader = reader_factory.read_from_index(2).await.unwrap();
+    assert_eq!(positions[2], reader.position());
+    match reader_factory.read_from_index(0).await {
+        Err(disklog::reader::Error::PositionRemoved(first)) => assert_eq!(positions[2], first),
+        _ => panic!("Should not be able to read removed items"),
+    }
+
+    std::mem::drop(opened.writer);
+    std::mem::drop(reader_factory);
+    let opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    assert_eq!(6, opened.reader_factory.item_count().await.unwrap());
+    let reader = opened.reader_factory.read_from_index(3).await.unwrap();
+    assert_eq!(positions[3], reader.position());
+}
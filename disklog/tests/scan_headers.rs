@@ -0,0 +1,58 @@
+use futures::TryStreamExt;
+use tempdir::TempDir;
+
+#[tokio::test]
+async fn scan_headers() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 100 * 1024,
+        ..Default::default()
+    };
+    let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+
+    // Larger than the scan buffer, so some are skipped by seeking, across several segments
+    let mut expected = Vec::new();
+    for i in 0..10usize {
+        let mut metadata = disklog::Metadata::new();
+        metadata.insert("index", i.to_string());
+        let contents = vec![i as u8; i * 20 * 1024 + 1];
+        let position = opened
+            .writer
+            .append_with_metadata(&metadata, &mut contents.as_slice())
+            .await
+            .unwrap();
+        expected.push((position, contents.len() as u32, metadata));
+    }
+
+    let headers: Vec<disklog::scan::ItemHeader> = opened
+        .reader_factory
+        .scan_headers(0)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(expected.len(), headers.len());
+    for (seq, (header, (position, len, metadata))) in
+        headers.iter().zip(expected.iter()).enumerate()
+    {
+        assert_eq!(*position, header.position);
+        assert_eq!(seq as u64, header.seq);
+        assert_eq!(*len, header.len);
+        assert_eq!(metadata, &header.metadata);
+    }
+
+    // From the middle of the log
+    let headers: Vec<disklog::scan::ItemHeader> = opened
+        .reader_factory
+        .scan_headers(expected[7].0)
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(3, headers.len());
+    assert_eq!(7, headers[0].seq);
+}
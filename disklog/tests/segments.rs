@@ -0,0 +1,56 @@
+use tempdir::TempDir;
+
+mod common;
+
+const MSGS: [&str; 6] = ["one", "two", "three", "four", "five", "six"];
+
+fn small_segments() -> disklog::LogOptions {
+    disklog::LogOptions {
+        segment_size: 10,
+        ..Default::default()
+    }
+}
+
+async fn read_all(reader_factory: &disklog::reader::ReaderFactory, from: u64) -> Vec<String> {
+    let reader = reader_factory.read_from(from).await.unwrap();
+    common::contents(common::read_items(reader, false).await)
+}
+
+#[tokio::test]
+async fn segments() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+
+    let mut positions = Vec::new();
+    {
+        let mut opened = disklog::open_log_with_options(&temp_dir, &small_segments())
+            .await
+            .unwrap();
+        for msg in MSGS[..3].iter() {
+            positions.push(opened.writer.append(&mut msg.as_bytes()).await.unwrap());
+        }
+    }
+
+    let mut opened = disklog::open_log_with_options(&temp_dir, &small_segments())
+        .await
+        .unwrap();
+    assert!(opened.recovery.is_none());
+    for msg in MSGS[3..].iter() {
+        positions.push(opened.writer.append(&mut msg.as_bytes()).await.unwrap());
+    }
+
+    let segment_files = std::fs::read_dir(&temp_dir)
+        .unwrap()
+        .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("log".as_ref()))
+        .count();
+    assert_eq!(
+        MSGS.len(),
+        segment_files,
+        "Each item should be in its own segment"
+    );
+
+    assert_eq!(MSGS.to_vec(), read_all(&opened.reader_factory, 0).await);
+    assert_eq!(
+        MSGS[2..].to_vec(),
+        read_all(&opened.reader_factory, positions[2]).await
+    );
+}
@@ -0,0 +1,76 @@
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const ITEMS: u64 = 5;
+
+async fn assert_indexed(reader_factory: &disklog::reader::ReaderFactory) {
+    assert_eq!(ITEMS, reader_factory.item_count().await.unwrap());
+
+    let reader = reader_factory.read_from_index(2).await.unwrap();
+    assert_eq!(2, reader.seq());
+    let mut item = reader.next(false).await.unwrap().unwrap();
+    assert_eq!(2, item.seq());
+    let mut contents = String::new();
+    item.read_to_string(&mut contents).await.unwrap();
+    assert_eq!("Message 2", contents);
+
+    let position = item.position();
+    let item = item.finish().next(false).await.unwrap().unwrap();
+    assert_eq!(3, item.seq());
+
+    let reader = reader_factory.read_from(position).await.unwrap();
+    assert_eq!(2, reader.seq());
+
+    let reader = reader_factory.read_from_index(ITEMS).await.unwrap();
+    assert!(reader.next(false).await.unwrap().is_end());
+
+    match reader_factory.read_from_index(ITEMS + 1).await {
+        Err(disklog::reader::Error::SequenceNotFound(count)) => assert_eq!(ITEMS, count),
+        _ => panic!("Expected the sequence number to be after the end"),
+    }
+}
+
+#[tokio::test]
+async fn read_from_index() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 40,
+        ..Default::default()
+    };
+
+    {
+        let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+            .await
+            .unwrap();
+        for i in 0..ITEMS {
+            opened
+                .writer
+                .append(&mut format!("Message {}", i).as_bytes())
+                .await
+                .unwrap();
+        }
+        assert_indexed(&opened.reader_factory).await;
+    }
+
+    // Missing entries are added when the log is opened
+    let index_path = temp_dir.path().join("offsetindex");
+    let index_len = std::fs::metadata(&index_path).unwrap().len();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&index_path)
+        .unwrap()
+        .set_len(index_len - 12)
+        .unwrap();
+    {
+        let opened = disklog::open_log_with_options(&temp_dir, &options)
+            .await
+            .unwrap();
+        assert_indexed(&opened.reader_factory).await;
+    }
+
+    std::fs::remove_file(&index_path).unwrap();
+    let opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    assert_indexed(&opened.reader_factory).await;
+}
@@ -0,0 +1,119 @@
+// Counts open descriptors through /proc
+#![cfg(target_os = "linux")]
+
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const MSGS: [&str; 3] = ["Hello World", "Another Message", "Third Message"];
+
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").unwrap().count()
+}
+
+/// Keeps `count` readers part way through an item, and as many more waiting at the tail.
+async fn start_readers(
+    reader_factory: &disklog::reader::ReaderFactory,
+    count: usize,
+) -> (
+    Vec<disklog::reader::LogItem>,
+    Vec<tokio::task::JoinHandle<()>>,
+) {
+    let mut items = Vec::new();
+    let mut waiting = Vec::new();
+    for _ in 0..count {
+        let reader = reader_factory.read_from(0).await.unwrap();
+        let mut item = reader.next(false).await.unwrap().unwrap();
+        let mut start = [0u8; 5];
+        item.read_exact(&mut start).await.unwrap();
+        assert_eq!(&MSGS[0].as_bytes()[..5], start);
+        items.push(item);
+
+        let reader = reader_factory
+            .read_from(reader_factory.tail_position())
+            .await
+            .unwrap();
+        waiting.push(tokio::spawn(async move {
+            reader.next(true).await.unwrap();
+        }));
+    }
+    tokio::task::yield_now().await;
+    (items, waiting)
+}
+
+#[tokio::test]
+async fn fds_do_not_grow_with_readers() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    // One item per segment, so readers use every segment
+    let options = disklog::LogOptions {
+        segment_size: 10,
+        ..Default::default()
+    };
+    let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    for msg in MSGS.iter() {
+        opened.writer.append(&mut msg.as_bytes()).await.unwrap();
+    }
+
+    // Read every segment once so its shared handle is open
+    let mut reader = opened.reader_factory.read_from(0).await.unwrap();
+    for msg in MSGS.iter() {
+        let mut item = reader.next(false).await.unwrap().unwrap();
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(*msg, contents);
+        reader = item.finish();
+    }
+
+    let (few_items, few_waiting) = start_readers(&opened.reader_factory, 10).await;
+    let fds_with_few = open_fds();
+    let (many_items, many_waiting) = start_readers(&opened.reader_factory, 500).await;
+    let fds_with_many = open_fds();
+    assert_eq!(fds_with_few, fds_with_many);
+
+    // Every waiting reader sees the next item
+    opened.writer.append(&mut b"Last".as_ref()).await.unwrap();
+    for waiting in few_waiting.into_iter().chain(many_waiting) {
+        waiting.await.unwrap();
+    }
+    for mut item in few_items.into_iter().chain(many_items) {
+        let mut rest = String::new();
+        item.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(MSGS[0][5..], rest);
+    }
+}
+
+#[tokio::test]
+async fn shared_offset_index_sees_later_appends() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    opened.writer.append(&mut MSGS[0].as_bytes()).await.unwrap();
+    assert_eq!(1, opened.reader_factory.item_count().await.unwrap());
+    // Opens the shared segment handle
+    let reader = opened.reader_factory.read_from_index(0).await.unwrap();
+    reader.next(false).await.unwrap().unwrap();
+
+    let fds_before = open_fds();
+    for msg in MSGS[1..].iter() {
+        opened.writer.append(&mut msg.as_bytes()).await.unwrap();
+    }
+    assert_eq!(3, opened.reader_factory.item_count().await.unwrap());
+
+    for (seq, msg) in MSGS.iter().enumerate() {
+        let reader = opened
+            .reader_factory
+            .read_from_index(seq as u64)
+            .await
+            .unwrap();
+        let mut item = reader.next(false).await.unwrap().unwrap();
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(*msg, contents);
+        assert_eq!(seq as u64, item.seq());
+    }
+    let reader = opened.reader_factory.read_from_last(1).await.unwrap();
+    let item = reader.next(false).await.unwrap().unwrap();
+    assert_eq!(2, item.seq());
+    drop(item);
+    assert_eq!(fds_before, open_fds());
+}
@@ -0,0 +1,94 @@
+use futures::{StreamExt, TryStreamExt};
+use tempdir::TempDir;
+
+mod common;
+use common::append_all;
+
+const MSGS: [&str; 4] = ["Hello World", "Another Message", "Third Message", "Fourth"];
+
+async fn contents(
+    reader_factory: &disklog::reader::ReaderFactory,
+    options: disklog::reader::StreamOptions,
+) -> Vec<String> {
+    let reader = reader_factory.read_from(0).await.unwrap();
+    let items: Vec<disklog::reader::StreamItem> =
+        reader.into_stream(options).try_collect().await.unwrap();
+    items
+        .into_iter()
+        .map(|item| String::from_utf8(item.contents.to_vec()).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn stream_items() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    let positions = append_all(&mut opened.writer, &MSGS).await;
+
+    let reader = opened.reader_factory.read_from(0).await.unwrap();
+    let items: Vec<disklog::reader::StreamItem> = reader
+        .into_stream(Default::default())
+        .try_collect()
+        .await
+        .unwrap();
+    assert_eq!(MSGS.len(), items.len());
+    for (seq, (item, msg)) in items.iter().zip(MSGS.iter()).enumerate() {
+        assert_eq!(positions[seq], item.position);
+        assert_eq!(seq as u64, item.seq);
+        assert_eq!(msg.as_bytes(), &item.contents[..]);
+    }
+}
+
+#[tokio::test]
+async fn stream_stops() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+    let positions = append_all(&mut opened.writer, &MSGS).await;
+    let factory = &opened.reader_factory;
+
+    let options = disklog::reader::StreamOptions {
+        max_items: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(MSGS[..2], contents(factory, options).await[..]);
+
+    let options = disklog::reader::StreamOptions {
+        end_before: Some(positions[3]),
+        ..Default::default()
+    };
+    assert_eq!(MSGS[..3], contents(factory, options).await[..]);
+
+    let options = disklog::reader::StreamOptions {
+        end_before_seq: Some(1),
+        ..Default::default()
+    };
+    assert_eq!(MSGS[..1], contents(factory, options).await[..]);
+
+    let options = disklog::reader::StreamOptions {
+        end_after: Some(positions[2]),
+        ..Default::default()
+    };
+    assert_eq!(MSGS[..3], contents(factory, options).await[..]);
+}
+
+#[tokio::test]
+async fn stream_waits_for_more() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+
+    let reader = opened.reader_factory.read_from(0).await.unwrap();
+    let options = disklog::reader::StreamOptions {
+        wait_for_more: true,
+        ..Default::default()
+    };
+    let mut stream = Box::pin(reader.into_stream(options));
+
+    let position = opened.writer.append(&mut MSGS[0].as_bytes()).await.unwrap();
+    let item = stream.next().await.unwrap().unwrap();
+    assert_eq!(position, item.position);
+    assert_eq!(MSGS[0].as_bytes(), &item.contents[..]);
+
+    // Ends once the writer is dropped
+    std::mem::drop(opened);
+    assert!(stream.next().await.is_none());
+}
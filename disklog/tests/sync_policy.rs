@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const MSGS: [&str; 3] = ["Hello World", "Another Message", "Last Message!"];
+
+#[tokio::test]
+async fn appends_survive_reopen_with_every_sync_policy() {
+    let policies = [
+        disklog::SyncPolicy::Always,
+        disklog::SyncPolicy::Interval(Duration::from_secs(3600)),
+        disklog::SyncPolicy::Bytes(1024 * 1024),
+        disklog::SyncPolicy::Never,
+    ];
+
+    for sync_policy in policies.iter() {
+        let temp_dir = TempDir::new("test-db").unwrap();
+        let options = disklog::LogOptions {
+            segment_size: 32,
+            sync_policy: *sync_policy,
+            ..Default::default()
+        };
+
+        {
+            let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+                .await
+                .unwrap();
+            for msg in MSGS.iter() {
+                opened.writer.append(&mut msg.as_bytes()).await.unwrap();
+            }
+            opened.writer.sync_if_due().await.unwrap();
+        }
+
+        let opened = disklog::open_log_with_options(&temp_dir, &options)
+            .await
+            .unwrap();
+        assert!(opened.recovery.is_none());
+
+        let mut reader = opened.reader_factory.read_from(0).await.unwrap();
+        for msg in MSGS.iter() {
+            let mut item = reader.next(false).await.unwrap().unwrap();
+            let mut contents = String::new();
+            item.read_to_string(&mut contents).await.unwrap();
+            assert_eq!(*msg, contents, "Item lost with {:?}", sync_policy);
+            reader = item.finish();
+        }
+    }
+}
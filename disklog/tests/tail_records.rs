@@ -0,0 +1,75 @@
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+const MSGS: [&str; 3] = ["first", "second", "third"];
+
+/// The size of the tail file header and of each tail record.
+const FILE_HEADER_SIZE: usize = 20;
+const TAIL_RECORD_SIZE: usize = 20;
+
+fn generation(record: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&record[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+#[tokio::test]
+async fn torn_tail_record_uses_previous() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let positions = {
+        let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+        let mut positions = Vec::new();
+        for msg in MSGS.iter() {
+            positions.push(opened.writer.append(&mut msg.as_bytes()).await.unwrap());
+        }
+        positions
+    };
+
+    // Damage the newest record, as if the last commit was torn
+    let tail_path = temp_dir.path().join("tail");
+    let mut tail = std::fs::read(&tail_path).unwrap();
+    assert_eq!(FILE_HEADER_SIZE + 2 * TAIL_RECORD_SIZE, tail.len());
+    let (first, second) = tail[FILE_HEADER_SIZE..].split_at_mut(TAIL_RECORD_SIZE);
+    let newest = if generation(first) > generation(second) {
+        first
+    } else {
+        second
+    };
+    newest[TAIL_RECORD_SIZE - 1] ^= 0xff;
+    std::fs::write(&tail_path, tail).unwrap();
+
+    // The last item was never committed
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    let recovery = opened.recovery.expect("Last item to be discarded");
+    assert_eq!(positions[2], recovery.tail_pos);
+
+    let mut reader = opened.reader_factory.read_from(0).await.unwrap();
+    for msg in MSGS[..2].iter() {
+        let mut item = reader.next(false).await.unwrap().unwrap();
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(*msg, contents);
+        reader = item.finish();
+    }
+    assert!(reader.next(false).await.unwrap().is_end());
+}
+
+#[tokio::test]
+async fn both_tail_records_damaged() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    {
+        let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+        opened.writer.append(&mut "first".as_bytes()).await.unwrap();
+    }
+
+    let tail_path = temp_dir.path().join("tail");
+    let mut tail = std::fs::read(&tail_path).unwrap();
+    tail[FILE_HEADER_SIZE] ^= 0xff;
+    tail[FILE_HEADER_SIZE + TAIL_RECORD_SIZE] ^= 0xff;
+    std::fs::write(&tail_path, tail).unwrap();
+
+    assert!(matches!(
+        disklog::open_log(&temp_dir).await,
+        Err(disklog::OpenError::CorruptTailPosition)
+    ));
+}
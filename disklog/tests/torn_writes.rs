@@ -0,0 +1,140 @@
+use std::io::Write;
+
+use tempdir::TempDir;
+use tokio::io::AsyncReadExt;
+
+#[tokio::test]
+async fn torn_write_is_truncated() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let segment_path = temp_dir.path().join("00000000000000000000.log");
+
+    let first = {
+        let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+        opened.writer.append(&mut "first".as_bytes()).await.unwrap()
+    };
+    let committed_len = std::fs::metadata(&segment_path).unwrap().len();
+
+    // Part of an item written before a crash
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&segment_path)
+        .unwrap()
+        .write_all(b"torn")
+        .unwrap();
+
+    let tail_pos = {
+        let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+        let recovery = opened.recovery.expect("Torn write to be discarded");
+        assert_eq!(4, recovery.discarded_bytes);
+        assert_eq!(
+            committed_len,
+            std::fs::metadata(&segment_path).unwrap().len()
+        );
+
+        let second = opened
+            .writer
+            .append(&mut "second".as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(recovery.tail_pos, second);
+        second
+    };
+
+    // Only the first open after the crash has anything to recover
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    assert!(opened.recovery.is_none());
+
+    let mut reader = opened.reader_factory.read_from(first).await.unwrap();
+    for (position, expected) in [(first, "first"), (tail_pos, "second")].iter() {
+        let mut item = reader.next(false).await.unwrap().unwrap();
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!((*position, *expected), (item.position(), contents.as_str()));
+        reader = item.finish();
+    }
+    assert!(reader.next(false).await.unwrap().is_end());
+}
+
+#[tokio::test]
+async fn uncommitted_segment_is_removed() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let tail_pos = {
+        let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+        opened.writer.append(&mut "first".as_bytes()).await.unwrap();
+        let segment_path = temp_dir.path().join("00000000000000000000.log");
+        std::fs::metadata(segment_path).unwrap().len() - 20
+    };
+
+    // A segment started after the tail, with a file header and part of an item
+    let stale_path = temp_dir.path().join(format!("{:020}.log", tail_pos + 100));
+    std::fs::write(&stale_path, [0u8; 20 + 6]).unwrap();
+
+    let opened = disklog::open_log(&temp_dir).await.unwrap();
+    assert_eq!(
+        Some(disklog::RecoveryReport {
+            discarded_bytes: 6,
+            tail_pos,
+            tail_rebuilt: false,
+            segment_recreated: false,
+            unindexed_from: None,
+        }),
+        opened.recovery
+    );
+    assert!(!stale_path.exists());
+}
+
+#[tokio::test]
+async fn segment_without_header_is_recreated() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let options = disklog::LogOptions {
+        segment_size: 1,
+        ..Default::default()
+    };
+    let (first, tail_pos) = {
+        let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+            .await
+            .unwrap();
+        let first = opened.writer.append(&mut "first".as_bytes()).await.unwrap();
+        (first, opened.reader_factory.tail_position())
+    };
+
+    // A crash while rolling over, before the whole header of the next segment was written
+    let next_path = temp_dir.path().join(format!("{:020}.log", tail_pos));
+    std::fs::write(&next_path, b"DLOG").unwrap();
+
+    {
+        let mut opened = disklog::open_log_with_options(&temp_dir, &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(disklog::RecoveryReport {
+                discarded_bytes: 0,
+                tail_pos,
+                tail_rebuilt: false,
+                segment_recreated: true,
+                unindexed_from: None,
+            }),
+            opened.recovery
+        );
+        let second = opened
+            .writer
+            .append(&mut "second".as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(tail_pos, second);
+    }
+
+    let opened = disklog::open_log_with_options(&temp_dir, &options)
+        .await
+        .unwrap();
+    assert!(opened.recovery.is_none());
+    let mut reader = opened.reader_factory.read_from(first).await.unwrap();
+    for expected in ["first", "second"].iter() {
+        let mut item = reader.next(false).await.unwrap().unwrap();
+        let mut contents = String::new();
+        item.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(*expected, contents);
+        reader = item.finish();
+    }
+    assert!(reader.next(false).await.unwrap().is_end());
+}
@@ -0,0 +1,85 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use tempdir::TempDir;
+
+mod common;
+
+const MSGS: [&str; 3] = ["Hello World", "Another Message", "Third Message"];
+
+/// Writes the messages one per segment, returning their positions.
+async fn write_log(path: &std::path::Path) -> Vec<u64> {
+    let options = disklog::LogOptions {
+        segment_size: 10,
+        ..Default::default()
+    };
+    common::write_log(path, &options, &MSGS).await.1
+}
+
+fn overwrite(path: &std::path::Path, offset: u64, bytes: &[u8]) {
+    let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(bytes).unwrap();
+}
+
+#[tokio::test]
+async fn verify_good_log() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    write_log(temp_dir.path()).await;
+
+    let report = disklog::verify(&temp_dir).await.unwrap();
+    assert!(report.is_ok());
+    assert_eq!(MSGS.len() as u64, report.items);
+    assert_eq!(MSGS.len() as u64, report.segments);
+    assert_eq!(0, report.first_position);
+}
+
+#[tokio::test]
+async fn verify_corrupt_contents() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let positions = write_log(temp_dir.path()).await;
+
+    // Flip the last byte of the second item's contents, before its 6 byte trailer
+    let segment_path = temp_dir.path().join(format!("{:020}.log", positions[1]));
+    let len = std::fs::metadata(&segment_path).unwrap().len();
+    overwrite(&segment_path, len - 6 - 1, b"!");
+
+    let report = disklog::verify(&temp_dir).await.unwrap();
+    assert!(!report.is_ok());
+    assert_eq!(1, report.items);
+    assert_eq!(
+        Some((positions[1], disklog::Corruption::Payload)),
+        report.first_bad
+    );
+}
+
+#[tokio::test]
+async fn verify_corrupt_trailer() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    let positions = write_log(temp_dir.path()).await;
+
+    // The trailer is the last 6 bytes of the segment, damage its length
+    let segment_path = temp_dir.path().join(format!("{:020}.log", positions[1]));
+    let len = std::fs::metadata(&segment_path).unwrap().len();
+    overwrite(&segment_path, len - 1, b"!");
+
+    let report = disklog::verify(&temp_dir).await.unwrap();
+    assert_eq!(
+        Some((positions[1], disklog::Corruption::Trailer)),
+        report.first_bad
+    );
+}
+
+#[tokio::test]
+async fn verify_tail_records() {
+    let temp_dir = TempDir::new("test-db").unwrap();
+    write_log(temp_dir.path()).await;
+
+    // The header is 20 bytes, then two 20 byte tail records. Three commits leave the newest in
+    // the second slot, so damage the first
+    overwrite(&temp_dir.path().join("tail"), 20 + 8, &[0xff; 8]);
+
+    let report = disklog::verify(&temp_dir).await.unwrap();
+    assert!(!report.tail_records_valid);
+    assert!(report.first_bad.is_none());
+    assert_eq!(MSGS.len() as u64, report.items);
+}
@@ -0,0 +1,104 @@
+use bytes::{Buf, BytesMut};
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, Request};
+
+/// Reads the whole body of a batch, or returns `None` if it is larger than `max_bytes`. The
+/// `Content-Length` is checked first so most large batches are refused without reading them.
+pub async fn read_batch_body(
+    req: Request<Body>,
+    max_bytes: u64,
+) -> Result<Option<Bytes>, hyper::Error> {
+    let content_length = req
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.is_some_and(|len| len > max_bytes) {
+        return Ok(None);
+    }
+
+    let mut body = req.into_body();
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if (buf.len() + chunk.len()) as u64 > max_bytes {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(Some(buf.freeze()))
+}
+
+/// Splits a body framed like the read stream, `| Item ID | Item data length | Item data |`,
+/// into the data of each item. The item IDs are ignored as the log assigns new ones.
+pub fn parse_batch(mut body: Bytes) -> Option<Vec<Bytes>> {
+    let mut items = Vec::new();
+    while body.has_remaining() {
+        if body.remaining() < 8 + 4 {
+            return None;
+        }
+        body.advance(8);
+        let len = body.get_u32() as usize;
+        if body.remaining() < len {
+            return None;
+        }
+        items.push(body.split_to(len));
+    }
+    Some(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+
+    fn frame(items: &[&[u8]]) -> Bytes {
+        let mut buf = Vec::new();
+        for item in items {
+            buf.put_u64(0);
+            buf.put_u32(item.len() as u32);
+            buf.put_slice(item);
+        }
+        buf.into()
+    }
+
+    #[test]
+    fn parse_items() {
+        let items = parse_batch(frame(&[b"one", b"", b"three"])).unwrap();
+        assert_eq!(vec![&b"one"[..], &b""[..], &b"three"[..]], items);
+        assert_eq!(Some(vec![]), parse_batch(Bytes::new()));
+    }
+
+    #[test]
+    fn parse_truncated_items() {
+        let body = frame(&[b"one", b"two"]);
+        assert_eq!(None, parse_batch(body.slice(..body.len() - 1)));
+        assert_eq!(None, parse_batch(body.slice(..10)));
+    }
+
+    #[tokio::test]
+    async fn refuse_large_bodies() {
+        let body = frame(&[b"one", b"two"]);
+        let req = Request::new(Body::from(body.clone()));
+        assert_eq!(
+            Some(body.clone()),
+            read_batch_body(req, body.len() as u64).await.unwrap()
+        );
+
+        let req = Request::new(Body::from(body.clone()));
+        assert_eq!(None, read_batch_body(req, 10).await.unwrap());
+
+        // A length which is too small is not trusted
+        let req = Request::builder()
+            .header(hyper::header::CONTENT_LENGTH, "10")
+            .body(Body::from(body.clone()))
+            .unwrap();
+        assert_eq!(None, read_batch_body(req, 20).await.unwrap());
+
+        let req = Request::builder()
+            .header(hyper::header::CONTENT_LENGTH, "1000")
+            .body(Body::from(body))
+            .unwrap();
+        assert_eq!(None, read_batch_body(req, 100).await.unwrap());
+    }
+}
@@ -36,7 +36,7 @@ impl AsyncRead for BodyReader {
                     Bytes::new()
                 };
                 // left_over gets mutated above!
-                buf.put_slice(&*left_over);
+                buf.put_slice(&left_over);
                 if !next_left_over.is_empty() {
                     br.left_over = Some(next_left_over);
                 }
@@ -48,8 +48,7 @@ impl AsyncRead for BodyReader {
                 body.poll_data(cx).map(|maybe_result| match maybe_result {
                     None => Ok(()),
                     Some(Err(e)) => {
-                        let wrapper_error =
-                            std::io::Error::new(std::io::ErrorKind::Other, Box::new(e));
+                        let wrapper_error = std::io::Error::other(Box::new(e));
                         Err(wrapper_error)
                     }
                     Some(Ok(mut bytes)) => {
@@ -58,7 +57,7 @@ impl AsyncRead for BodyReader {
                         } else {
                             Bytes::new()
                         };
-                        buf.put_slice(&*bytes);
+                        buf.put_slice(&bytes);
                         if !next_left_over.is_empty() {
                             br.left_over = Some(next_left_over);
                         }
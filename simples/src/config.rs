@@ -1,19 +1,55 @@
+use std::collections::HashMap;
 use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::topicname::TopicName;
 
 pub type ConfigError = String;
 
 const KEY_PREFIX: &str = "SIMPLES_";
 
+/// Separates a log option key from the topic it applies to, eg `SIMPLES_SEGMENT_SIZE__orders`.
+const TOPIC_SEPARATOR: &str = "__";
+
 const ADDRESS_KEY: &str = "ADDRESS";
+/// The largest body in bytes accepted by the batch endpoint, applies to every topic.
+const MAX_BATCH_SIZE_KEY: &str = "MAX_BATCH_SIZE";
+/// Milliseconds to wait for another server to stop using the topics directory, and for a topic
+/// to be closed when opening it. Applies to every topic.
+const LOCK_TIMEOUT_KEY: &str = "LOCK_TIMEOUT";
+
+const SEGMENT_SIZE_KEY: &str = "SEGMENT_SIZE";
+const RETENTION_MAX_BYTES_KEY: &str = "RETENTION_MAX_BYTES";
+const RETENTION_MAX_AGE_KEY: &str = "RETENTION_MAX_AGE";
+const RETENTION_MAX_ITEMS_KEY: &str = "RETENTION_MAX_ITEMS";
+const SYNC_KEY: &str = "SYNC";
+const REBUILD_TAIL_KEY: &str = "REBUILD_TAIL";
+
+const LOG_OPTION_KEYS: [&str; 6] = [
+    SEGMENT_SIZE_KEY,
+    RETENTION_MAX_BYTES_KEY,
+    RETENTION_MAX_AGE_KEY,
+    RETENTION_MAX_ITEMS_KEY,
+    SYNC_KEY,
+    REBUILD_TAIL_KEY,
+];
 
 pub struct Config {
     pub address: String,
+    pub max_batch_size: u64,
+    /// Used for every topic without its own log options.
+    pub log_options: disklog::LogOptions,
+    pub topic_log_options: HashMap<TopicName, disklog::LogOptions>,
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
             address: "0.0.0.0:3000".to_string(),
+            max_batch_size: 64 * 1024 * 1024,
+            log_options: disklog::LogOptions::default(),
+            topic_log_options: HashMap::new(),
         }
     }
 }
@@ -28,6 +64,49 @@ fn try_from_env(key: &str) -> Result<Option<String>, ConfigError> {
     }
 }
 
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T, ConfigError> {
+    value
+        .parse()
+        .map_err(|_| format!("Could not parse config key '{}'", key))
+}
+
+/// Accepts `always`, `never`, a number of milliseconds like `100ms` or of bytes like `4096bytes`.
+fn parse_sync_policy(key: &str, value: &str) -> Result<disklog::SyncPolicy, ConfigError> {
+    if value == "always" {
+        Ok(disklog::SyncPolicy::Always)
+    } else if value == "never" {
+        Ok(disklog::SyncPolicy::Never)
+    } else if let Some(millis) = value.strip_suffix("ms") {
+        Ok(disklog::SyncPolicy::Interval(Duration::from_millis(
+            parse_value(key, millis)?,
+        )))
+    } else if let Some(bytes) = value.strip_suffix("bytes") {
+        Ok(disklog::SyncPolicy::Bytes(parse_value(key, bytes)?))
+    } else {
+        Err(format!("Could not parse config key '{}'", key))
+    }
+}
+
+/// Returns false if the key is not a log option.
+fn set_log_option(
+    options: &mut disklog::LogOptions,
+    key: &str,
+    value: &str,
+) -> Result<bool, ConfigError> {
+    match key {
+        SEGMENT_SIZE_KEY => options.segment_size = parse_value(key, value)?,
+        RETENTION_MAX_BYTES_KEY => options.retention.max_bytes = Some(parse_value(key, value)?),
+        RETENTION_MAX_AGE_KEY => {
+            options.retention.max_age = Some(Duration::from_secs(parse_value(key, value)?))
+        }
+        RETENTION_MAX_ITEMS_KEY => options.retention.max_items = Some(parse_value(key, value)?),
+        SYNC_KEY => options.sync_policy = parse_sync_policy(key, value)?,
+        REBUILD_TAIL_KEY => options.rebuild_tail = parse_value(key, value)?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
 impl Config {
     pub fn from_env() -> Result<Config, ConfigError> {
         let mut config = Config::default();
@@ -36,6 +115,119 @@ impl Config {
             config.address = address;
         };
 
+        let max_batch_size_key = format!("{}{}", KEY_PREFIX, MAX_BATCH_SIZE_KEY);
+        if let Some(max_batch_size) = try_from_env(&max_batch_size_key)? {
+            config.max_batch_size = parse_value(&max_batch_size_key, &max_batch_size)?;
+        };
+
+        let lock_timeout_key = format!("{}{}", KEY_PREFIX, LOCK_TIMEOUT_KEY);
+        if let Some(millis) = try_from_env(&lock_timeout_key)? {
+            config.log_options.lock_timeout = Some(Duration::from_millis(parse_value(
+                &lock_timeout_key,
+                &millis,
+            )?));
+        };
+
+        for key in LOG_OPTION_KEYS.iter() {
+            if let Some(value) = try_from_env(&format!("{}{}", KEY_PREFIX, key))? {
+                set_log_option(&mut config.log_options, key, &value)?;
+            }
+        }
+
+        // Topic specific options start from the server wide ones
+        for (env_key, value) in env::vars_os() {
+            let env_key = match env_key.into_string() {
+                Ok(env_key) => env_key,
+                Err(_) => continue,
+            };
+            let (key, topic) = match env_key
+                .strip_prefix(KEY_PREFIX)
+                .and_then(|key| key.split_once(TOPIC_SEPARATOR))
+            {
+                Some(key_and_topic) => key_and_topic,
+                None => continue,
+            };
+
+            let topic_name = TopicName::parse(topic)
+                .ok_or_else(|| format!("Invalid topic name in config key '{}'", env_key))?;
+            let value = value
+                .into_string()
+                .map_err(|_| format!("Could not parse config key '{}'", env_key))?;
+            let defaults = &config.log_options;
+            let options = config
+                .topic_log_options
+                .entry(topic_name)
+                .or_insert_with(|| defaults.clone());
+            if !set_log_option(options, key, &value)? {
+                return Err(format!("Unknown config key '{}'", env_key));
+            }
+        }
+
         Ok(config)
     }
+
+    pub fn log_options(&self, topic_name: &TopicName) -> &disklog::LogOptions {
+        self.topic_log_options
+            .get(topic_name)
+            .unwrap_or(&self.log_options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_retention_options() {
+        let mut options = disklog::LogOptions::default();
+        assert_eq!(
+            Ok(true),
+            set_log_option(&mut options, "RETENTION_MAX_AGE", "60")
+        );
+        assert_eq!(
+            Ok(true),
+            set_log_option(&mut options, "RETENTION_MAX_ITEMS", "5")
+        );
+        assert_eq!(
+            Ok(false),
+            set_log_option(&mut options, "ADDRESS", "0.0.0.0:80")
+        );
+        assert!(set_log_option(&mut options, "RETENTION_MAX_BYTES", "lots").is_err());
+        assert_eq!(Some(Duration::from_secs(60)), options.retention.max_age);
+        assert_eq!(Some(5), options.retention.max_items);
+        assert_eq!(None, options.retention.max_bytes);
+    }
+
+    #[test]
+    fn parse_sync_policies() {
+        assert_eq!(
+            Ok(disklog::SyncPolicy::Always),
+            parse_sync_policy("SYNC", "always")
+        );
+        assert_eq!(
+            Ok(disklog::SyncPolicy::Never),
+            parse_sync_policy("SYNC", "never")
+        );
+        assert_eq!(
+            Ok(disklog::SyncPolicy::Interval(Duration::from_millis(100))),
+            parse_sync_policy("SYNC", "100ms")
+        );
+        assert_eq!(
+            Ok(disklog::SyncPolicy::Bytes(4096)),
+            parse_sync_policy("SYNC", "4096bytes")
+        );
+        assert!(parse_sync_policy("SYNC", "sometimes").is_err());
+    }
+
+    #[test]
+    fn set_rebuild_tail() {
+        let mut options = disklog::LogOptions::default();
+        assert!(!options.rebuild_tail);
+        assert_eq!(
+            Ok(true),
+            set_log_option(&mut options, "REBUILD_TAIL", "true")
+        );
+        assert!(options.rebuild_tail);
+        assert!(set_log_option(&mut options, "REBUILD_TAIL", "yes").is_err());
+    }
 }
@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::error::BoxedError;
+
+/// Not a valid topic name, so never mistaken for a topic.
+const LOCK_FILE_NAME: &str = ".lock";
+
+/// An exclusive lock on the topics directory, held until dropped.
+pub struct DataLock {
+    _file: File,
+}
+
+pub enum LockError {
+    /// Another process holds the lock.
+    Locked,
+    Io(BoxedError),
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockError::Locked => write!(f, "The topics directory is in use by another process"),
+            LockError::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+/// Locks the topics directory, creating it if needed, so two servers never share topics. If
+/// another process already has it locked, for example a server which is being restarted, this
+/// waits up to `timeout` for it to stop.
+pub async fn lock_topics(
+    topics_path: &Path,
+    timeout: Option<Duration>,
+) -> Result<DataLock, LockError> {
+    tokio::fs::create_dir_all(topics_path)
+        .await
+        .map_err(|e| LockError::Io(Box::new(e)))?;
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(topics_path.join(LOCK_FILE_NAME))
+        .await
+        .map_err(|e| LockError::Io(Box::new(e)))?
+        .into_std()
+        .await;
+
+    // Locked the same way as logs
+    match disklog::lock_file_with_timeout(&file, timeout).await {
+        Ok(()) => Ok(DataLock { _file: file }),
+        Err(disklog::OpenError::AlreadyOpen) => Err(LockError::Locked),
+        Err(e) => Err(LockError::Io(Box::new(e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn second_lock_fails() {
+        let temp_dir = TempDir::new("test-topics").unwrap();
+        let topics_path = temp_dir.path().join("topics");
+
+        let lock = lock_topics(&topics_path, None).await.ok().unwrap();
+        assert!(matches!(
+            lock_topics(&topics_path, None).await,
+            Err(LockError::Locked)
+        ));
+        assert!(matches!(
+            lock_topics(&topics_path, Some(Duration::from_millis(100))).await,
+            Err(LockError::Locked)
+        ));
+
+        std::mem::drop(lock);
+        assert!(lock_topics(&topics_path, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn wait_for_lock() {
+        let temp_dir = TempDir::new("test-topics").unwrap();
+        let topics_path = temp_dir.path().join("topics");
+
+        let lock = lock_topics(&topics_path, None).await.ok().unwrap();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            std::mem::drop(lock);
+        });
+        assert!(lock_topics(&topics_path, Some(Duration::from_secs(10)))
+            .await
+            .is_ok());
+    }
+}
@@ -1,18 +1,24 @@
+mod batch;
 mod bodyreader;
+mod datalock;
 mod error;
 mod config;
+mod metadata;
+mod migrate;
 mod query;
 mod read;
+mod salvage;
 mod topicname;
+mod verify;
 
 use std::collections::HashMap;
-use std::ops::DerefMut;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
 
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
-use tokio::sync::RwLock;
+use tokio::sync::{OnceCell, RwLock};
 
 use crate::bodyreader::BodyReader;
 use crate::error::BoxedError;
@@ -20,18 +26,29 @@ use crate::read::ReadOptions;
 use crate::topicname::TopicName;
 
 struct TopicState {
-    writer: RwLock<disklog::writer::Writer>,
+    writer: disklog::group::GroupWriter,
     reader_factory: disklog::reader::ReaderFactory,
+    /// What was discarded when the topic was opened, if it had not been shut down cleanly.
+    recovery: Option<disklog::RecoveryReport>,
 }
 
+/// A topic's state, set once its log has been opened.
+type TopicSlot = Arc<OnceCell<Arc<TopicState>>>;
+
 struct ServerState {
-    topics: RwLock<HashMap<TopicName, Arc<TopicState>>>,
+    config: config::Config,
+    topics: RwLock<HashMap<TopicName, TopicSlot>>,
 }
 
+const TOPICS_PATH: &str = "topics";
+
+/// How often idle topics are checked against their retention policy.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+
 const INVALID_TOPIC_NAME: &str = "Invalid topic name";
 const TOPIC_NOT_FOUND: &str = "Topic not found";
 
-fn parse_path_parts<'a>(path: &'a str) -> Box<[&'a str]> {
+fn parse_path_parts(path: &str) -> Box<[&str]> {
     let mut path_parts = path.split('/').skip(1).collect::<Vec<_>>();
     if path_parts.last() == Some(&"") {
         path_parts.pop();
@@ -55,7 +72,7 @@ async fn create_topic(_req: Request<Body>, name: &str) -> Result<Response<Body>,
         }
     };
 
-    let topic_path = Path::new("topics").join(topic_name.to_str());
+    let topic_path = Path::new(TOPICS_PATH).join(topic_name.to_str());
 
     let metadata = tokio::fs::metadata(&topic_path).await;
     let body = if metadata.is_ok() {
@@ -74,42 +91,141 @@ async fn open_or_create_topic_state(
     topic_name: &TopicName,
 ) -> Result<Option<Arc<TopicState>>, BoxedError> {
     {
-        if let Some(topic_state) = server_state.topics.read().await.get(topic_name) {
+        let topics = server_state.topics.read().await;
+        if let Some(topic_state) = topics.get(topic_name).and_then(|slot| slot.get()) {
             return Ok(Some(topic_state.clone()));
         }
     }
 
-    let topic_path = Path::new("topics").join(topic_name.to_str());
+    let topic_path = Path::new(TOPICS_PATH).join(topic_name.to_str());
     let metadata = tokio::fs::metadata(&topic_path).await;
     if metadata.is_ok() {
-        let open_result = disklog::open_log(&topic_path).await?;
-        if open_result.recovered {
-            eprintln!("Recovered log: '{:?}'", topic_path);
-        }
-        let topic_state = Arc::new(TopicState {
-            writer: RwLock::new(open_result.writer),
-            reader_factory: open_result.reader_factory,
-        });
-        server_state
+        let slot = server_state
             .topics
             .write()
             .await
-            .insert(topic_name.clone(), topic_state.clone());
-        Ok(Some(topic_state))
+            .entry(topic_name.clone())
+            .or_default()
+            .clone();
+        // Opening can wait for the log's lock or read all of its items, so it happens outside
+        // the topics lock and only holds up requests for this topic
+        let topic_state = slot
+            .get_or_try_init(|| open_topic_state(server_state, topic_name, &topic_path))
+            .await?;
+        Ok(Some(topic_state.clone()))
     } else {
         Ok(None)
     }
 }
 
+async fn open_topic_state(
+    server_state: &ServerState,
+    topic_name: &TopicName,
+    topic_path: &Path,
+) -> Result<Arc<TopicState>, BoxedError> {
+    let open_options = server_state.config.log_options(topic_name);
+    let open_result = match disklog::open_log_with_options(topic_path, open_options).await {
+        Ok(open_result) => open_result,
+        Err(disklog::OpenError::CorruptTailPosition) => {
+            eprintln!(
+                "Log '{:?}' has a lost or corrupt tail file, set SIMPLES_REBUILD_TAIL=true to rebuild it",
+                topic_path
+            );
+            return Err(Box::new(disklog::OpenError::CorruptTailPosition));
+        }
+        Err(disklog::OpenError::UnsupportedVersion(version)) => {
+            eprintln!(
+                "Log '{:?}' uses format version {}, run 'simples migrate' to upgrade it",
+                topic_path, version
+            );
+            return Err(Box::new(disklog::OpenError::UnsupportedVersion(version)));
+        }
+        Err(e) => return Err(Box::new(e)),
+    };
+    if let Some(recovery) = open_result.recovery {
+        if recovery.tail_rebuilt {
+            eprintln!(
+                "Rebuilt the tail file of log '{:?}' by reading its items",
+                topic_path
+            );
+        }
+        if recovery.segment_recreated {
+            eprintln!(
+                "Recreated the last segment of log '{:?}', its file header was incomplete",
+                topic_path
+            );
+        }
+        if let Some(position) = recovery.unindexed_from {
+            eprintln!(
+                "Log '{:?}' has a corrupt item at {}, the items after it can only be salvaged",
+                topic_path, position
+            );
+        }
+        eprintln!(
+            "Recovered log: '{:?}', discarded {} bytes after the tail at {}",
+            topic_path, recovery.discarded_bytes, recovery.tail_pos
+        );
+    }
+    let topic_state = Arc::new(TopicState {
+        writer: disklog::group::GroupWriter::new(open_result.writer).await?,
+        reader_factory: open_result.reader_factory,
+        recovery: open_result.recovery,
+    });
+    if let disklog::SyncPolicy::Interval(period) = open_options.sync_policy {
+        if period > Duration::from_millis(0) {
+            tokio::spawn(sync_idle_topic(
+                topic_name.clone(),
+                Arc::downgrade(&topic_state),
+                period,
+            ));
+        }
+    }
+    Ok(topic_state)
+}
+
 async fn write_body(
-    writer: &mut disklog::writer::Writer,
+    writer: &disklog::group::GroupWriter,
+    expected_tail: Option<disklog::LogPosition>,
+    metadata: &disklog::Metadata,
     body: Body,
 ) -> Result<Response<Body>, BoxedError> {
     println!("Writing body");
-    let pos = writer.append(&mut BodyReader::new(body)).await?;
-    Ok(Response::builder()
-        .header("Content-Type", "application/json")
-        .body(pos.to_string().into())?)
+    let mut contents = BodyReader::new(body);
+    let result = match expected_tail {
+        Some(expected_tail) => {
+            writer
+                .append_if_with_metadata(expected_tail, metadata, &mut contents)
+                .await
+        }
+        None => writer.append_with_metadata(metadata, &mut contents).await,
+    };
+    match result {
+        Ok(pos) => Ok(Response::builder()
+            .header("Content-Type", "application/json")
+            .body(pos.to_string().into())?),
+        Err(disklog::writer::Error::TailMoved(tail_pos)) => Ok(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .header("Content-Type", "application/json")
+            .body(tail_pos.to_string().into())?),
+        Err(disklog::writer::Error::MetadataTooLarge) => Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Metadata too large".into())?),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Reads the `expected_tail` option from the query string of an append.
+fn parse_expected_tail(query_string: &str) -> Option<Option<disklog::LogPosition>> {
+    let mut query = query::parse_query_string(query_string)?;
+    let expected_tail = match query.remove("expected_tail") {
+        Some(expected_tail) => Some(expected_tail.parse().ok()?),
+        None => None,
+    };
+    if query.is_empty() {
+        Some(expected_tail)
+    } else {
+        None
+    }
 }
 
 async fn append_item(
@@ -126,9 +242,24 @@ async fn append_item(
         }
     };
 
+    let expected_tail = match parse_expected_tail(req.uri().query().unwrap_or("")) {
+        Some(expected_tail) => expected_tail,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("Could not read options".into())?)
+        }
+    };
+
     if let Some(topic_state) = open_or_create_topic_state(&server_state, &topic_name).await? {
-        let mut writer = topic_state.writer.write().await;
-        write_body(writer.deref_mut(), req.into_body()).await
+        let metadata = metadata::from_headers(req.headers());
+        write_body(
+            &topic_state.writer,
+            expected_tail,
+            &metadata,
+            req.into_body(),
+        )
+        .await
     } else {
         Ok(Response::builder()
             .status(StatusCode::BAD_REQUEST)
@@ -136,6 +267,78 @@ async fn append_item(
     }
 }
 
+async fn append_batch(
+    req: Request<Body>,
+    server_state: Arc<ServerState>,
+    name: &str,
+) -> Result<Response<Body>, BoxedError> {
+    let topic_name = match topicname::TopicName::parse(name) {
+        Some(topic_name) => topic_name,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(INVALID_TOPIC_NAME.into())?)
+        }
+    };
+
+    let topic_state = match open_or_create_topic_state(&server_state, &topic_name).await? {
+        Some(topic_state) => topic_state,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(TOPIC_NOT_FOUND.into())?)
+        }
+    };
+
+    let body = match batch::read_batch_body(req, server_state.config.max_batch_size).await? {
+        Some(body) => body,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body("Batch is too large".into())?)
+        }
+    };
+    let mut items = match batch::parse_batch(body) {
+        Some(items) => items
+            .into_iter()
+            .map(std::io::Cursor::new)
+            .collect::<Vec<_>>(),
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("Could not read batch".into())?)
+        }
+    };
+
+    let positions = topic_state.writer.append_batch(&mut items).await?;
+    let ids = positions
+        .iter()
+        .map(|pos| pos.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    Ok(Response::builder()
+        .header("Content-Type", "application/json")
+        .body(format!("[{}]", ids).into())?)
+}
+
+/// The response for an error starting to read a topic.
+fn read_error_response(error: disklog::reader::Error) -> Result<Response<Body>, BoxedError> {
+    match error {
+        disklog::reader::Error::PositionRemoved(first) => Ok(Response::builder()
+            .status(StatusCode::GONE)
+            .header("Content-Type", "application/json")
+            .body(first.to_string().into())?),
+        disklog::reader::Error::MisalignedPosition => Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Position is not the ID of an item".into())?),
+        disklog::reader::Error::SequenceNotFound(count) => Ok(Response::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header("Content-Type", "application/json")
+            .body(count.to_string().into())?),
+        e => Err(Box::new(e)),
+    }
+}
+
 async fn read_items(
     req: Request<Body>,
     server_state: Arc<ServerState>,
@@ -154,9 +357,27 @@ async fn read_items(
 
     if let Some(options) = options {
         if let Some(topic_state) = open_or_create_topic_state(&server_state, &topic_name).await? {
-            let from = options.from.unwrap_or(0);
-            let reader = topic_state.reader_factory.read_from(from).await?;
-            Ok(Response::new(read::read_to_body(reader, options)))
+            let reader_factory = &topic_state.reader_factory;
+            if options.backward {
+                let reader = match options.from {
+                    Some(from) => reader_factory.read_backwards_from(from).await,
+                    None => reader_factory.read_backwards_from_tail().await,
+                };
+                return match reader {
+                    Ok(reader) => Ok(Response::new(read::read_backwards_to_body(reader, options))),
+                    Err(e) => read_error_response(e),
+                };
+            }
+            let reader = match (options.from_time, options.from_seq, options.from_last) {
+                (Some(from_time), _, _) => reader_factory.read_from_time(from_time).await,
+                (_, Some(from_seq), _) => reader_factory.read_from_index(from_seq).await,
+                (_, _, Some(count)) => reader_factory.read_from_last(count).await,
+                _ => reader_factory.read_from(options.from.unwrap_or(0)).await,
+            };
+            match reader {
+                Ok(reader) => Ok(Response::new(read::read_to_body(reader, options))),
+                Err(e) => read_error_response(e),
+            }
         } else {
             Ok(Response::builder()
                 .status(StatusCode::BAD_REQUEST)
@@ -169,6 +390,123 @@ async fn read_items(
     }
 }
 
+async fn salvage_items(
+    req: Request<Body>,
+    server_state: Arc<ServerState>,
+    name: &str,
+) -> Result<Response<Body>, BoxedError> {
+    let from =
+        query::parse_query_string(req.uri().query().unwrap_or("")).and_then(salvage::parse_from);
+    let topic_name = match topicname::TopicName::parse(name) {
+        Some(topic_name) => topic_name,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(INVALID_TOPIC_NAME.into())?)
+        }
+    };
+
+    if let Some(from) = from {
+        if let Some(topic_state) = open_or_create_topic_state(&server_state, &topic_name).await? {
+            let reader_factory = &topic_state.reader_factory;
+            let from = from.unwrap_or_else(|| reader_factory.first_position());
+            match reader_factory.read_from(from).await {
+                Ok(reader) => Ok(Response::new(salvage::salvage_to_body(reader))),
+                Err(e) => read_error_response(e),
+            }
+        } else {
+            Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(TOPIC_NOT_FOUND.into())?)
+        }
+    } else {
+        Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Could not read options".into())?)
+    }
+}
+
+async fn recovery_report(
+    server_state: Arc<ServerState>,
+    name: &str,
+) -> Result<Response<Body>, BoxedError> {
+    let topic_name = match topicname::TopicName::parse(name) {
+        Some(topic_name) => topic_name,
+        None => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(INVALID_TOPIC_NAME.into())?)
+        }
+    };
+
+    if let Some(topic_state) = open_or_create_topic_state(&server_state, &topic_name).await? {
+        let report = match topic_state.recovery {
+            Some(recovery) => format!(
+                "{{\"discarded_bytes\":{},\"tail_pos\":{},\"tail_rebuilt\":{},\"segment_recreated\":{},\"unindexed_from\":{}}}",
+                recovery.discarded_bytes,
+                recovery.tail_pos,
+                recovery.tail_rebuilt,
+                recovery.segment_recreated,
+                recovery
+                    .unindexed_from
+                    .map_or("null".to_string(), |position| position.to_string())
+            ),
+            None => "null".to_string(),
+        };
+        Ok(Response::builder()
+            .header("Content-Type", "application/json")
+            .body(report.into())?)
+    } else {
+        Ok(Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(TOPIC_NOT_FOUND.into())?)
+    }
+}
+
+async fn enforce_retention(server_state: Arc<ServerState>) {
+    let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        let topics: Vec<(TopicName, Arc<TopicState>)> = server_state
+            .topics
+            .read()
+            .await
+            .iter()
+            .filter_map(|(topic_name, slot)| {
+                let topic_state = slot.get()?;
+                Some((topic_name.clone(), topic_state.clone()))
+            })
+            .collect();
+
+        for (topic_name, topic_state) in topics {
+            if let Err(e) = topic_state.writer.enforce_retention().await {
+                eprintln!(
+                    "Error enforcing retention for topic '{}': {}",
+                    topic_name.to_str(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Syncs appends a topic's interval sync policy left unsynced when appends stopped.
+async fn sync_idle_topic(topic_name: TopicName, topic_state: Weak<TopicState>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+
+        let topic_state = match topic_state.upgrade() {
+            Some(topic_state) => topic_state,
+            None => return,
+        };
+        if let Err(e) = topic_state.writer.sync_if_due().await {
+            eprintln!("Error syncing topic '{}': {}", topic_name.to_str(), e);
+        }
+    }
+}
+
 async fn handle(
     req: Request<Body>,
     server_state: Arc<ServerState>,
@@ -185,10 +523,22 @@ async fn handle(
             let name = name.to_string();
             append_item(req, server_state, name.as_ref()).await
         }
+        (&Method::POST, ["topic", name, "batch"]) => {
+            let name = name.to_string();
+            append_batch(req, server_state, name.as_ref()).await
+        }
         (&Method::GET, ["topic", name, "items"]) => {
             let name = name.to_string();
             read_items(req, server_state, name.as_ref()).await
         }
+        (&Method::GET, ["admin", "topic", name, "recovery"]) => {
+            let name = name.to_string();
+            recovery_report(server_state, name.as_ref()).await
+        }
+        (&Method::GET, ["admin", "topic", name, "salvage"]) => {
+            let name = name.to_string();
+            salvage_items(req, server_state, name.as_ref()).await
+        }
         _ => Ok(Response::builder()
             .status(StatusCode::NOT_FOUND)
             .body("404".into())
@@ -198,6 +548,49 @@ async fn handle(
 
 #[tokio::main]
 async fn main() {
+    match std::env::args().nth(1).as_deref() {
+        None => serve().await,
+        Some("migrate") => {
+            let _lock = lock_topics(None).await;
+            if let Err(e) = migrate::migrate_topics(Path::new(TOPICS_PATH)).await {
+                eprintln!("Error migrating topics: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some("verify") => match verify::verify_topics(Path::new(TOPICS_PATH)).await {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error verifying topics: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(command) => {
+            eprintln!("Unknown command '{}'", command);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exits if another server is using the topics directory and does not stop within `timeout`.
+async fn lock_topics(timeout: Option<Duration>) -> datalock::DataLock {
+    match datalock::lock_topics(Path::new(TOPICS_PATH), timeout).await {
+        Ok(lock) => lock,
+        Err(datalock::LockError::Locked) => {
+            eprintln!(
+                "Another simples process is already using the '{}' directory, stop it first",
+                TOPICS_PATH
+            );
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Error locking the '{}' directory: {}", TOPICS_PATH, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn serve() {
     let config = match config::Config::from_env() {
         Ok(config) => config,
         Err(error) => {
@@ -214,12 +607,17 @@ async fn main() {
         }
     };
 
+    let _lock = lock_topics(config.log_options.lock_timeout).await;
+
     println!("Launching simples {} on {}:{}", env!("CARGO_PKG_VERSION"), addr.ip(), addr.port());
 
     let server_state = Arc::new(ServerState {
+        config,
         topics: RwLock::new(HashMap::new()),
     });
 
+    tokio::spawn(enforce_retention(server_state.clone()));
+
     let make_svc = make_service_fn(move |_conn| {
         let server_state = server_state.clone();
         async move {
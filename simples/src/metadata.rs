@@ -0,0 +1,36 @@
+use hyper::header::{HeaderMap, CONTENT_TYPE};
+
+/// Request headers starting with this are stored as item metadata, without the prefix.
+const META_HEADER_PREFIX: &str = "x-simples-meta-";
+
+/// Collects the item metadata from the headers of an append request. Header names are lower case.
+pub fn from_headers(headers: &HeaderMap) -> disklog::Metadata {
+    let mut metadata = disklog::Metadata::new();
+    for (name, value) in headers {
+        if name == CONTENT_TYPE {
+            metadata.insert(name.as_str(), value.as_bytes());
+        } else if let Some(key) = name.as_str().strip_prefix(META_HEADER_PREFIX) {
+            metadata.insert(key, value.as_bytes());
+        }
+    }
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn metadata_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+        headers.insert("X-Simples-Meta-Event", HeaderValue::from_static("created"));
+        headers.insert("User-Agent", HeaderValue::from_static("curl"));
+
+        let metadata = from_headers(&headers);
+        assert_eq!(2, metadata.len());
+        assert_eq!(Some(&b"application/json"[..]), metadata.get("content-type"));
+        assert_eq!(Some(&b"created"[..]), metadata.get("event"));
+    }
+}
@@ -0,0 +1,27 @@
+use std::path::Path;
+
+use crate::error::BoxedError;
+use crate::topicname::TopicName;
+
+/// Upgrades every topic in the topics directory to the current log format.
+pub async fn migrate_topics(topics_path: &Path) -> Result<(), BoxedError> {
+    let mut entries = tokio::fs::read_dir(topics_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let topic_name = match file_name.to_str().and_then(TopicName::parse) {
+            Some(topic_name) => topic_name,
+            None => continue,
+        };
+
+        match disklog::migrate::migrate_log(entry.path()).await? {
+            Some(migration) => println!(
+                "Migrated topic '{}' from format version {}, copied {} items",
+                topic_name.to_str(),
+                migration.from_version,
+                migration.items
+            ),
+            None => println!("Topic '{}' is up to date", topic_name.to_str()),
+        }
+    }
+    Ok(())
+}
@@ -3,7 +3,7 @@ use std::collections::HashMap;
 pub type ParsedQuery<'a> = HashMap<&'a str, &'a str>;
 
 pub fn parse_query_string<'a>(query_string: &'a str) -> Option<ParsedQuery<'a>> {
-    if query_string == "" {
+    if query_string.is_empty() {
         return Some(HashMap::new());
     }
 
@@ -1,71 +1,185 @@
-use bytes::BufMut;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{BufMut, BytesMut};
+use futures::TryStreamExt;
 use hyper::body::Bytes;
 use hyper::Body;
 use tokio::io::AsyncReadExt;
 
 use disklog::reader;
-use disklog::LogPosition;
+use disklog::{LogPosition, Metadata, SequenceNumber};
 
+use crate::error::BoxedError;
 use crate::query::ParsedQuery;
-use crate::BoxedError;
+
+/// The most of an item's contents sent in one chunk of the response body.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
 
 pub struct ReadOptions {
     pub from: Option<LogPosition>,
+    /// Start this many items before the tail, instead of `from`. Given as `from=tail` or `from=-N`.
+    pub from_last: Option<u64>,
+    /// Start from the first item appended at or after this time, instead of `from`.
+    pub from_time: Option<SystemTime>,
+    /// Start from the item with this sequence number, instead of `from`.
+    pub from_seq: Option<SequenceNumber>,
     pub end_before: Option<LogPosition>,
+    pub end_before_seq: Option<SequenceNumber>,
     pub end_after: Option<LogPosition>,
     pub max_items: Option<usize>,
     pub wait_for_more: bool,
+    /// Frame each item with its metadata.
+    pub include_metadata: bool,
+    /// Frame each item with its sequence number.
+    pub include_seq: bool,
+    /// Read the items before `from`, or before the tail, newest first.
+    pub backward: bool,
 }
 
 impl ReadOptions {
     pub fn from_query(mut query: ParsedQuery) -> Option<ReadOptions> {
         let mut options = ReadOptions {
             from: None,
+            from_last: None,
+            from_time: None,
+            from_seq: None,
             end_before: None,
+            end_before_seq: None,
             end_after: None,
             max_items: None,
             wait_for_more: false,
+            include_metadata: false,
+            include_seq: false,
+            backward: false,
         };
         for (k, v) in query.drain() {
             match k {
-                "from" => options.from = Some(v.parse().ok()?),
+                "from" if v == "tail" => options.from_last = Some(0),
+                "from" => match v.strip_prefix('-') {
+                    Some(count) => options.from_last = Some(count.parse().ok()?),
+                    None => options.from = Some(v.parse().ok()?),
+                },
+                "from_time" => {
+                    options.from_time = Some(UNIX_EPOCH + Duration::from_millis(v.parse().ok()?))
+                }
+                "from_seq" => options.from_seq = Some(v.parse().ok()?),
                 "end_before" => options.end_before = Some(v.parse().ok()?),
+                "end_before_seq" => options.end_before_seq = Some(v.parse().ok()?),
                 "end_after" => options.end_after = Some(v.parse().ok()?),
                 "max_items" => options.max_items = Some(v.parse().ok()?),
                 "wait_for_more" => options.wait_for_more = v.parse().ok()?,
+                "include_metadata" => options.include_metadata = v.parse().ok()?,
+                "include_seq" => options.include_seq = v.parse().ok()?,
+                "direction" => {
+                    options.backward = match v {
+                        "forward" => false,
+                        "backward" => true,
+                        _ => None?,
+                    }
+                }
                 _ => None?,
             }
         }
+        let starts = [
+            options.from.is_some(),
+            options.from_last.is_some(),
+            options.from_time.is_some(),
+            options.from_seq.is_some(),
+        ];
+        if starts.iter().filter(|start| **start).count() > 1 {
+            return None;
+        }
+        // Reading backwards only supports starting from an item ID or the tail and a maximum
+        // number of items
+        let backward_unsupported = options.from_last.is_some_and(|count| count > 0)
+            || options.from_time.is_some()
+            || options.from_seq.is_some()
+            || options.end_before.is_some()
+            || options.end_before_seq.is_some()
+            || options.end_after.is_some()
+            || options.wait_for_more;
+        if options.backward && backward_unsupported {
+            return None;
+        }
         Some(options)
     }
 }
 
+impl ReadOptions {
+    fn stream_options(&self) -> reader::StreamOptions {
+        reader::StreamOptions {
+            max_items: self.max_items,
+            end_before: self.end_before,
+            end_before_seq: self.end_before_seq,
+            end_after: self.end_after,
+            wait_for_more: self.wait_for_more,
+        }
+    }
+}
+
+/// Frames the start of an item with the fields requested in the options, up to its contents.
+fn item_header(
+    options: &ReadOptions,
+    position: LogPosition,
+    seq: SequenceNumber,
+    metadata: &Metadata,
+    len: u32,
+) -> BytesMut {
+    let mut buf = BytesMut::new();
+    buf.put_u64(position);
+    if options.include_seq {
+        buf.put_u64(seq);
+    }
+    if options.include_metadata {
+        let metadata = metadata.encode().expect("Stored metadata to fit");
+        buf.put_u16(metadata.len() as u16);
+        buf.put_slice(&metadata);
+    }
+    buf.put_u32(len);
+    buf
+}
+
+/// Frames an item which was read in full.
+fn item_bytes(options: &ReadOptions, item: reader::StreamItem) -> Bytes {
+    let mut buf = item_header(
+        options,
+        item.position,
+        item.seq,
+        &item.metadata,
+        item.contents.len() as u32,
+    );
+    buf.put_slice(&item.contents);
+    buf.freeze()
+}
+
 enum ReaderStream {
-    Between(ReadOptions, reader::Reader),
-    Reading(ReadOptions, reader::LogItem),
+    Between(ReadOptions, reader::StreamOptions, reader::Reader),
+    Reading(ReadOptions, reader::StreamOptions, reader::LogItem),
 }
 
+/// Reads the next chunk of an item's contents into `buf`, which might already hold its header.
 async fn read_log_item_bytes(
-    start_new_item: bool,
+    mut buf: BytesMut,
     options: ReadOptions,
+    stream_options: reader::StreamOptions,
     mut log_item: reader::LogItem,
 ) -> Result<(Bytes, ReaderStream), BoxedError> {
-    let mut buf = Vec::new();
-    if start_new_item {
-        buf.put_u64(log_item.position());
-        buf.put_u32(log_item.len());
+    buf.reserve(log_item.left_to_read().min(READ_CHUNK_SIZE));
+    while buf.len() < buf.capacity() && log_item.left_to_read() > 0 {
+        log_item.read_buf(&mut buf).await?;
     }
-    log_item.read_buf(&mut buf).await?;
-
-    let body_bytes = hyper::body::Bytes::from(buf);
 
     if log_item.left_to_read() == 0 {
+        let reader = log_item.finish();
         Ok((
-            body_bytes,
-            ReaderStream::Between(options, log_item.finish()),
+            buf.freeze(),
+            ReaderStream::Between(options, stream_options, reader),
         ))
     } else {
-        Ok((body_bytes, ReaderStream::Reading(options, log_item)))
+        Ok((
+            buf.freeze(),
+            ReaderStream::Reading(options, stream_options, log_item),
+        ))
     }
 }
 
@@ -73,46 +187,94 @@ async fn unfold_readerstream(
     rs: ReaderStream,
 ) -> Result<Option<(Bytes, ReaderStream)>, BoxedError> {
     match rs {
-        ReaderStream::Between(mut options, reader) => {
-            let end = options.max_items == Some(0)
-                || options.end_before == Some(reader.position())
-                || options
-                    .end_after
-                    .map_or(false, |pos| pos < reader.position());
-            if end {
-                Ok(None)
-            } else {
-                options.max_items = options.max_items.map(|max_items| max_items - 1);
-                match reader.next(options.wait_for_more).await? {
-                    reader::NextItem::Item(log_item) => {
-                        read_log_item_bytes(true, options, log_item).await.map(Some)
-                    }
-                    reader::NextItem::End(_) => Ok(None),
+        ReaderStream::Between(options, mut stream_options, reader) => {
+            match reader.next_with_options(&mut stream_options).await? {
+                reader::NextItem::Item(log_item) => {
+                    let buf = item_header(
+                        &options,
+                        log_item.position(),
+                        log_item.seq(),
+                        log_item.metadata(),
+                        log_item.len(),
+                    );
+                    read_log_item_bytes(buf, options, stream_options, log_item)
+                        .await
+                        .map(Some)
                 }
+                reader::NextItem::End(_) => Ok(None),
             }
         }
-        ReaderStream::Reading(options, log_item) => read_log_item_bytes(false, options, log_item)
-            .await
-            .map(Some),
+        ReaderStream::Reading(options, stream_options, log_item) => {
+            read_log_item_bytes(BytesMut::new(), options, stream_options, log_item)
+                .await
+                .map(Some)
+        }
     }
 }
 
+/// Streams the items to the body, sending large items in chunks rather than reading them into
+/// memory.
 pub fn read_to_body(reader: reader::Reader, options: ReadOptions) -> Body {
-    let stream =
-        futures::stream::try_unfold(ReaderStream::Between(options, reader), unfold_readerstream);
+    let stream_options = options.stream_options();
+    let stream = futures::stream::try_unfold(
+        ReaderStream::Between(options, stream_options, reader),
+        unfold_readerstream,
+    );
+
+    Body::wrap_stream(stream)
+}
+
+pub fn read_backwards_to_body(reader: reader::BackwardReader, options: ReadOptions) -> Body {
+    let stream = reader
+        .into_stream(options.max_items)
+        .map_ok(move |item| item_bytes(&options, item));
 
     Body::wrap_stream(stream)
 }
 
 #[cfg(test)]
 mod tests {
+    use futures::StreamExt;
+    use tempdir::TempDir;
+
     use crate::query::parse_query_string;
 
     use super::*;
 
+    #[tokio::test]
+    async fn stream_large_items_in_chunks() {
+        let temp_dir = TempDir::new("test-topic").unwrap();
+        let mut opened = disklog::open_log(&temp_dir).await.unwrap();
+        let large = vec![b'x'; 3 * READ_CHUNK_SIZE];
+        let first = opened.writer.append(&mut &large[..]).await.unwrap();
+        let second = opened.writer.append(&mut &b"small"[..]).await.unwrap();
+
+        let options = parse_query_string("")
+            .and_then(ReadOptions::from_query)
+            .expect("Valid parse");
+        let reader = opened.reader_factory.read_from(0).await.unwrap();
+        let mut body = read_to_body(reader, options);
+        let mut received = Vec::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.unwrap();
+            assert!(chunk.len() <= 2 * READ_CHUNK_SIZE);
+            received.extend_from_slice(&chunk);
+        }
+
+        let mut expected = BytesMut::new();
+        expected.put_u64(first);
+        expected.put_u32(large.len() as u32);
+        expected.put_slice(&large);
+        expected.put_u64(second);
+        expected.put_u32(5);
+        expected.put_slice(b"small");
+        assert_eq!(&expected[..], &received[..]);
+    }
+
     #[test]
     fn parse_all_options() {
-        let qs = "from=1&end_before=2&end_after=3&max_items=4&wait_for_more=true";
+        let qs =
+            "from=1&end_before=2&end_after=3&max_items=4&wait_for_more=true&include_metadata=true";
         let options = parse_query_string(qs)
             .and_then(ReadOptions::from_query)
             .expect("Valid parse");
@@ -120,6 +282,75 @@ mod tests {
         assert_eq!(Some(2), options.end_before);
         assert_eq!(Some(3), options.end_after);
         assert_eq!(Some(4), options.max_items);
-        assert_eq!(true, options.wait_for_more);
+        assert!(options.wait_for_more);
+        assert!(options.include_metadata);
+    }
+
+    #[test]
+    fn parse_from_time() {
+        let options = parse_query_string("from_time=1500")
+            .and_then(ReadOptions::from_query)
+            .expect("Valid parse");
+        assert_eq!(
+            Some(UNIX_EPOCH + Duration::from_millis(1500)),
+            options.from_time
+        );
+        assert!(parse_query_string("from=1&from_time=1500")
+            .and_then(ReadOptions::from_query)
+            .is_none());
+    }
+
+    #[test]
+    fn parse_seq_options() {
+        let options = parse_query_string("from_seq=3&end_before_seq=5&include_seq=true")
+            .and_then(ReadOptions::from_query)
+            .expect("Valid parse");
+        assert_eq!(Some(3), options.from_seq);
+        assert_eq!(Some(5), options.end_before_seq);
+        assert!(options.include_seq);
+        assert!(parse_query_string("from_seq=3&from_time=1500")
+            .and_then(ReadOptions::from_query)
+            .is_none());
+    }
+
+    #[test]
+    fn parse_from_tail() {
+        let options = parse_query_string("from=tail")
+            .and_then(ReadOptions::from_query)
+            .expect("Valid parse");
+        assert_eq!(Some(0), options.from_last);
+        assert_eq!(None, options.from);
+        let options = parse_query_string("from=-50")
+            .and_then(ReadOptions::from_query)
+            .expect("Valid parse");
+        assert_eq!(Some(50), options.from_last);
+        assert!(parse_query_string("from=-lots")
+            .and_then(ReadOptions::from_query)
+            .is_none());
+        assert!(parse_query_string("from=tail&from_seq=3")
+            .and_then(ReadOptions::from_query)
+            .is_none());
+        assert!(parse_query_string("from=-5&direction=backward")
+            .and_then(ReadOptions::from_query)
+            .is_none());
+    }
+
+    #[test]
+    fn parse_direction() {
+        let options = parse_query_string("direction=backward&max_items=50")
+            .and_then(ReadOptions::from_query)
+            .expect("Valid parse");
+        assert!(options.backward);
+        assert_eq!(Some(50), options.max_items);
+        let options = parse_query_string("direction=forward")
+            .and_then(ReadOptions::from_query)
+            .expect("Valid parse");
+        assert!(!options.backward);
+        assert!(parse_query_string("direction=sideways")
+            .and_then(ReadOptions::from_query)
+            .is_none());
+        assert!(parse_query_string("direction=backward&wait_for_more=true")
+            .and_then(ReadOptions::from_query)
+            .is_none());
     }
 }
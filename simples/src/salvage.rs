@@ -0,0 +1,104 @@
+use bytes::BufMut;
+use hyper::body::Bytes;
+use hyper::Body;
+use tokio::io::AsyncReadExt;
+
+use disklog::reader::{self, Recovered};
+use disklog::LogPosition;
+
+use crate::query::ParsedQuery;
+use crate::BoxedError;
+
+const ITEM_FRAME: u8 = 0;
+const SKIPPED_FRAME: u8 = 1;
+
+/// Parses the salvage query string, which only has the position to start from.
+pub fn parse_from(mut query: ParsedQuery) -> Option<Option<LogPosition>> {
+    let mut from = None;
+    for (k, v) in query.drain() {
+        match k {
+            "from" => from = Some(v.parse().ok()?),
+            _ => None?,
+        }
+    }
+    Some(from)
+}
+
+fn item_frame(position: LogPosition, contents: &[u8]) -> Bytes {
+    let mut buf = Vec::with_capacity(1 + 8 + 4 + contents.len());
+    buf.put_u8(ITEM_FRAME);
+    buf.put_u64(position);
+    buf.put_u32(contents.len() as u32);
+    buf.put_slice(contents);
+    Bytes::from(buf)
+}
+
+fn skipped_frame(start: LogPosition, end: LogPosition) -> Bytes {
+    let mut buf = Vec::with_capacity(1 + 8 + 8);
+    buf.put_u8(SKIPPED_FRAME);
+    buf.put_u64(start);
+    buf.put_u64(end);
+    Bytes::from(buf)
+}
+
+/// Reads the next item which can be salvaged, or the range of the log skipped to reach it.
+///
+/// Each item is read whole before it is sent so that an item with corrupt contents can be
+/// reported as skipped instead.
+async fn next_frame(reader: reader::Reader) -> Result<Option<(Bytes, reader::Reader)>, BoxedError> {
+    match reader.next_recovering(false).await? {
+        Recovered::Item(mut item) => {
+            let mut contents = Vec::with_capacity(item.len() as usize);
+            let result = item.read_to_end(&mut contents).await;
+            let position = item.position();
+            let reader = item.finish();
+            match result {
+                Ok(_) => Ok(Some((item_frame(position, &contents), reader))),
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    Ok(Some((skipped_frame(position, reader.position()), reader)))
+                }
+                Err(e) => Err(Box::new(e)),
+            }
+        }
+        Recovered::Skipped(range, reader) => {
+            Ok(Some((skipped_frame(range.start, range.end), reader)))
+        }
+        Recovered::End(_) => Ok(None),
+    }
+}
+
+/// Streams every item up to the tail which can still be read, skipping corrupt parts of the log.
+pub fn salvage_to_body(reader: reader::Reader) -> Body {
+    Body::wrap_stream(futures::stream::try_unfold(reader, next_frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query::parse_query_string;
+
+    use super::*;
+
+    #[test]
+    fn parse_salvage_options() {
+        assert_eq!(
+            Some(Some(5)),
+            parse_query_string("from=5").and_then(parse_from)
+        );
+        assert_eq!(Some(None), parse_query_string("").and_then(parse_from));
+        assert!(parse_query_string("max_items=1")
+            .and_then(parse_from)
+            .is_none());
+    }
+
+    #[test]
+    fn frames() {
+        assert_eq!(
+            &[0, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 2, b'h', b'i'][..],
+            &item_frame(7, b"hi")[..]
+        );
+        assert_eq!(
+            &[1, 0, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 0, 0, 0, 0, 9][..],
+            &skipped_frame(7, 9)[..]
+        );
+    }
+}
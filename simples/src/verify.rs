@@ -0,0 +1,35 @@
+use std::path::Path;
+
+use crate::error::BoxedError;
+use crate::topicname::TopicName;
+
+/// Checks every topic in the topics directory for corruption, returning false if any is found.
+pub async fn verify_topics(topics_path: &Path) -> Result<bool, BoxedError> {
+    let mut all_ok = true;
+    let mut entries = tokio::fs::read_dir(topics_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name();
+        let topic_name = match file_name.to_str().and_then(TopicName::parse) {
+            Some(topic_name) => topic_name,
+            None => continue,
+        };
+
+        let report = disklog::verify(entry.path()).await?;
+        println!(
+            "Topic '{}': {} items in {} segments from {} to {}",
+            topic_name.to_str(),
+            report.items,
+            report.segments,
+            report.first_position,
+            report.tail_pos
+        );
+        if !report.tail_records_valid {
+            println!("  A tail record is damaged");
+        }
+        if let Some((position, corruption)) = report.first_bad {
+            println!("  {} at {}", corruption, position);
+        }
+        all_ok &= report.is_ok();
+    }
+    Ok(all_ok)
+}